@@ -3,6 +3,12 @@ pub mod greeting
 	pub const WELCOME_MESSAGE: &'static str = "Welcome to the mud\r\nWhat's your name?\r\n";
 	pub const REGISTER_MESSAGE: &'static str = "This appears to your first time here, \r\nwould you like to visit us in the mud world?\r\n";
 	pub const ENTER_PASSWORD: &'static str = "Please enter your pass code\r\n";
+	pub const WRONG_PASSWORD: &'static str = "That pass code is not correct\r\n";
+	pub const ATTEMPTS_LEFT: &'static str = "Attempts left: ";
+	pub const TOO_MANY_ATTEMPTS: &'static str = "Too many failed attempts, goodbye\r\n";
+	pub const LOGON_SUCCESS: &'static str = "Welcome back\r\n";
+	pub const ALREADY_PLAYING: &'static str = "You are already playing, take over that session? (y/n)\r\n";
+	pub const KEEP_SESSION: &'static str = "Your other session keeps playing, goodbye\r\n";
 	pub const REGISTER_USERNAME: &'static str = "Please enter a username\r\n";
 	pub const REGISTER_PASSWORD: &'static str = "Password please:\r\n";
 	pub const CONFIRM_PASSWORD: &'static str = "Please confirm your password:\r\n";
@@ -16,4 +22,54 @@ pub mod character
 	pub const TYPESELECTION: &'static str = "What kind of {} would you like to be?\r\n[1]Intelligent\t\t[2]Atheletic\t\t[3]Average\r\n";
 	pub const ATTRSELECTION: &'static str = "Are you satisfied with the following attributes?\r\n";
 	pub const SUCCESS: &'static str = "Your character has been created\r\n";
+	pub const RESUME_CREATION: &'static str = "Your character is not finished yet, let's continue\r\n";
+}
+
+pub mod game
+{
+	pub const CHANNEL_USAGE: &'static str = "Usage: channel [<name> on|off]\r\n";
+	pub const NO_SUCH_PLAYER: &'static str = "Nobody by that name is playing\r\n";
+	pub const NO_EXIT: &'static str = "You can't go that way.\r\n";
+	pub const NOWHERE: &'static str = "You are floating in nothingness.\r\n";
+	pub const NOT_HERE: &'static str = "You don't see that here.\r\n";
+	pub const NOT_CARRYING: &'static str = "You are not carrying that.\r\n";
+	pub const NOBODY_HERE: &'static str = "They aren't here.\r\n";
+	pub const CANT_TAKE: &'static str = "You can't pick that up.\r\n";
+	pub const CANT_DROP: &'static str = "You can't let go of it.\r\n";
+	pub const TOO_HEAVY: &'static str = "You can't carry that much weight.\r\n";
+	pub const CANT_WEAR: &'static str = "You can't wear that.\r\n";
+	pub const NOT_WEARING: &'static str = "You are not wearing that.\r\n";
+	pub const ALREADY_FIGHTING: &'static str = "You are already fighting!\r\n";
+	pub const NOT_FIGHTING: &'static str = "You aren't fighting anyone.\r\n";
+	pub const KILL_SELF: &'static str = "You can't attack yourself.\r\n";
+	pub const PEACEFUL: &'static str = "Shopkeepers are under the protection of the town, leave them be.\r\n";
+	pub const STILL_FIGHTING: &'static str = "You can't leave while you are fighting, try to flee!\r\n";
+	pub const FLEE_FAILED: &'static str = "You try to get away but can't!\r\n";
+	pub const FLED: &'static str = "You flee head over heels!\r\n";
+	pub const YOU_DIED: &'static str = "You have been KILLED! You wake up somewhere safe.\r\n";
+	pub const UNKNOWN_SPELL: &'static str = "You don't know any spell by that name.\r\n";
+	pub const UNKNOWN_SKILL: &'static str = "You don't know how to do that.\r\n";
+	pub const NO_TARGET: &'static str = "Use it on whom?\r\n";
+	pub const TOO_TIRED: &'static str = "You are too tired.\r\n";
+	pub const NO_MANA: &'static str = "You don't have enough mana.\r\n";
+	pub const LOST_CONCENTRATION: &'static str = "You lose your concentration.\r\n";
+	pub const NO_PRACTICES: &'static str = "You have no practice sessions left.\r\n";
+	pub const CANT_TRAIN: &'static str = "You can train str, agi, int or charm.\r\n";
+	pub const NO_TRAINS: &'static str = "You have no training points left.\r\n";
+}
+
+pub mod connection
+{
+	pub const LINE_TOO_LONG: &'static str = "Your input was too long and has been discarded";
+	pub const SERVER_FULL: &'static str = "The server is full, try again later.\r\n";
+	pub const TOO_MANY_FROM_ADDRESS: &'static str = "Too many connections from your address, try again later.\r\n";
+	pub const IDLE_TIMEOUT: &'static str = "You have been idle too long, goodbye.\r\n";
+	pub const RECONNECTED: &'static str = "Reconnecting, you take back your character.\r\n";
+	pub const SHUTTING_DOWN: &'static str = "The server is shutting down, try again later.\r\n";
+	pub const SHUTDOWN_NOW: &'static str = "The server is shutting down now, goodbye.\r\n";
+	pub const COPYOVER_START: &'static str = "{WThe world is being rebuilt around you, hold on...{x\r\n";
+	pub const COPYOVER_DONE: &'static str = "{WThe world comes back into focus.{x\r\n";
+	pub const COPYOVER_FAILED: &'static str = "The rebuild failed, the world carries on as it was.\r\n";
+	pub const TAKEN_OVER: &'static str = "You logged in from somewhere else, this session is disconnected.\r\n";
+	pub const ALREADY_IN_WORLD: &'static str = "Your character is already in the world, goodbye.\r\n";
 }
\ No newline at end of file
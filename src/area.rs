@@ -0,0 +1,717 @@
+// Area files
+// Every *.toml file in the area directory describes rooms, item and mob prototypes and the
+// resets that place them. Each file is checked on its own, then exits and resets are checked
+// against everything that was loaded so they may point into other areas.
+
+extern crate toml;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+
+use self::toml::{Parser, Table, Value};
+
+use character::ATTRIBUTES;
+use equipment::WearSlot;
+use item::{ItemPrototype, ItemType, ITEM_FLAGS};
+use mob::{MobPrototype, BEHAVIOURS};
+use world::{Direction, Reset, ResetKind, Room, World, DEFAULT_ROOM};
+
+pub const AREA_PATH: &'static str = "./areas/";
+
+const AREA_FIELDS: [&'static str; 2] = ["name", "start"];
+const ROOM_FIELDS: [&'static str; 3] = ["title", "description", "exits"];
+const ITEM_FIELDS: [&'static str; 9] = ["name", "keywords", "description", "weight", "value", "type", "flags", "wear", "modifiers"];
+const MOB_FIELDS: [&'static str; 5] = ["name", "keywords", "description", "attributes", "behaviours"];
+const RESET_FIELDS: [&'static str; 4] = ["kind", "id", "room", "max"];
+
+/// A problem found in an area file, line is 0 when it is not known
+pub struct AreaError
+{
+	pub file: String,
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for AreaError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "{}:{}: {}", self.file, self.line, self.message)
+	}
+}
+
+/// Load every area in dir into one world.
+///
+/// Without any area files the built in world is used, so a fresh checkout still runs.
+pub fn load_areas(dir: &str) -> Result<World, Vec<AreaError>>
+{
+	let mut paths = Vec::new();
+	match fs::read_dir(dir)
+	{
+		Ok(entries) =>
+		{
+			for entry in entries
+			{
+				if let Ok(entry) = entry
+				{
+					let path = entry.path();
+					if path.extension().map(|ext| ext == "toml").unwrap_or(false)
+					{
+						paths.push(path);
+					}
+				}
+			}
+		},
+		Err(e) => println!("Could not read area directory {}: {}", dir, e),
+	}
+
+	if paths.is_empty()
+	{
+		println!("No area files in {}, using the built in world", dir);
+		return Ok(World::default_world());
+	}
+
+	paths.sort();
+	let mut loader = AreaLoader::new();
+	for path in paths
+	{
+		let file = path.to_string_lossy().into_owned();
+		let mut source = String::new();
+		match File::open(&path).and_then(|mut f| f.read_to_string(&mut source))
+		{
+			Ok(_) => loader.load(&file, &source),
+			Err(e) => loader.error(&file, 0, format!("could not read file: {}", e)),
+		}
+	}
+	loader.finish()
+}
+
+// 1-based line where section.id is defined, its own table header or an inline `id = { ... }`
+// under [section]. Falls back to the line of the section when neither is found.
+fn header_line(source: &str, section: &str, id: &str) -> usize
+{
+	let exact = format!("[{}.{}]", section, id);
+	let nested = format!("[{}.{}.", section, id);
+	let header = format!("[{}]", section);
+	let mut in_section = false;
+	for (index, line) in source.lines().enumerate()
+	{
+		let line = line.trim();
+		if line.starts_with(&exact) || line.starts_with(&nested)
+		{
+			return index + 1;
+		}
+		if line.starts_with('[')
+		{
+			in_section = line.starts_with(&header);
+		}
+		else if in_section && line.contains('=') && line.split('=').next().map(|key| key.trim().trim_matches('"')) == Some(id)
+		{
+			return index + 1;
+		}
+	}
+	section_line(source, section)
+}
+
+// 1-based line where a top level section starts, 0 when there is none
+fn section_line(source: &str, section: &str) -> usize
+{
+	let headers = [format!("[{}]", section), format!("[{}.", section), format!("[[{}]]", section)];
+	for (index, line) in source.lines().enumerate()
+	{
+		let line = line.trim();
+		if headers.iter().any(|header| line.starts_with(&header[..])) || line.split('=').next().map(|key| key.trim()) == Some(section)
+		{
+			return index + 1;
+		}
+	}
+	0
+}
+
+// 1-based line of the nth [[section]] header
+fn array_line(source: &str, section: &str, nth: usize) -> usize
+{
+	let header = format!("[[{}]]", section);
+	source.lines().enumerate()
+		.filter(|&(_, line)| line.trim().starts_with(&header))
+		.nth(nth)
+		.map(|(index, _)| index + 1)
+		.unwrap_or(0)
+}
+
+fn as_table<'a>(value: &'a Value, what: &str) -> Result<&'a Table, String>
+{
+	match *value
+	{
+		Value::Table(ref table) => Ok(table),
+		ref other => Err(format!("{} should be a table, not {}", what, other.type_str())),
+	}
+}
+
+// builders mistype field names, so anything unexpected is an error rather than ignored
+fn check_fields(entry: &Table, allowed: &[&'static str]) -> Result<(), String>
+{
+	for key in entry.keys()
+	{
+		if !allowed.contains(&&key[..])
+		{
+			return Err(format!("unknown field {}, expected one of: {}", key, allowed.join(", ")));
+		}
+	}
+	Ok(())
+}
+
+fn string_field(entry: &Table, key: &str) -> Result<Option<String>, String>
+{
+	match entry.get(key)
+	{
+		Some(&Value::String(ref value)) => Ok(Some(value.clone())),
+		Some(other) => Err(format!("{} should be a string, not {}", key, other.type_str())),
+		None => Ok(None),
+	}
+}
+
+fn required_string(entry: &Table, key: &str) -> Result<String, String>
+{
+	match try!(string_field(entry, key))
+	{
+		Some(value) => Ok(value),
+		None => Err(format!("{} is missing", key)),
+	}
+}
+
+fn integer_field(entry: &Table, key: &str, default: i32) -> Result<i32, String>
+{
+	match entry.get(key)
+	{
+		Some(&Value::Integer(value)) => Ok(value as i32),
+		Some(other) => Err(format!("{} should be a number, not {}", key, other.type_str())),
+		None => Ok(default),
+	}
+}
+
+// a list of lowercase words
+fn words_field(entry: &Table, key: &str) -> Result<Option<Vec<String>>, String>
+{
+	match entry.get(key)
+	{
+		Some(&Value::Array(ref values)) =>
+		{
+			let mut words = Vec::new();
+			for value in values
+			{
+				match value.as_str()
+				{
+					Some(word) => words.push(word.to_lowercase()),
+					None => return Err(format!("{} should be a list of strings", key)),
+				}
+			}
+			Ok(Some(words))
+		},
+		Some(other) => Err(format!("{} should be an array, not {}", key, other.type_str())),
+		None => Ok(None),
+	}
+}
+
+// keywords default to the words of the name
+fn keywords_field(entry: &Table, name: &str) -> Result<Vec<String>, String>
+{
+	match try!(words_field(entry, "keywords"))
+	{
+		Some(keywords) => Ok(keywords),
+		None => Ok(name.split_whitespace().map(|word| word.to_lowercase()).collect()),
+	}
+}
+
+// a table of attribute names and numbers, e.g. { str = 2, hp = 10 }
+fn attributes_field(entry: &Table, name: &str) -> Result<Vec<(String, i32)>, String>
+{
+	let mut attributes = Vec::new();
+	if let Some(table) = entry.get(name)
+	{
+		for (attribute, value) in try!(as_table(table, name))
+		{
+			if !ATTRIBUTES.contains(&&attribute[..])
+			{
+				return Err(format!("unknown attribute {}, expected one of: {}", attribute, ATTRIBUTES.join(", ")));
+			}
+			match value.as_integer()
+			{
+				Some(number) => attributes.push((attribute.clone(), number as i32)),
+				None => return Err(format!("{} {} should be a number", name, attribute)),
+			}
+		}
+	}
+	Ok(attributes)
+}
+
+fn parse_room(id: &str, value: &Value) -> Result<Room, String>
+{
+	let entry = try!(as_table(value, "a room"));
+	try!(check_fields(entry, &ROOM_FIELDS));
+
+	let mut room = Room::new(id, &try!(required_string(entry, "title")), &try!(required_string(entry, "description")));
+	if let Some(exits) = entry.get("exits")
+	{
+		for (name, to) in try!(as_table(exits, "exits"))
+		{
+			let direction = match Direction::parse(name)
+			{
+				Some(direction) => direction,
+				None => return Err(format!("unknown exit direction {}", name)),
+			};
+			match to.as_str()
+			{
+				Some(to) => room = room.exit(direction, to),
+				None => return Err(format!("exit {} should name a room", name)),
+			}
+		}
+	}
+	Ok(room)
+}
+
+fn parse_item(id: &str, value: &Value) -> Result<ItemPrototype, String>
+{
+	let entry = try!(as_table(value, "an item"));
+	try!(check_fields(entry, &ITEM_FIELDS));
+
+	let item_type = match try!(string_field(entry, "type"))
+	{
+		Some(name) => match ItemType::parse(&name)
+		{
+			Some(item_type) => item_type,
+			None => return Err(format!("unknown item type {}", name)),
+		},
+		None => ItemType::Other,
+	};
+
+	let flags = try!(words_field(entry, "flags")).unwrap_or(Vec::new());
+	for flag in flags.iter()
+	{
+		if !ITEM_FLAGS.contains(&&flag[..])
+		{
+			return Err(format!("unknown flag {}, expected one of: {}", flag, ITEM_FLAGS.join(", ")));
+		}
+	}
+
+	let wear = match try!(string_field(entry, "wear"))
+	{
+		Some(name) => match WearSlot::parse(&name)
+		{
+			Some(slot) => Some(slot),
+			None => return Err(format!("unknown wear location {}, expected head, body, hands, weapon, shield or ring", name)),
+		},
+		None => None,
+	};
+
+	let modifiers = try!(attributes_field(entry, "modifiers"));
+
+	let name = try!(required_string(entry, "name"));
+	Ok(ItemPrototype
+	{
+		id: id.to_string(),
+		keywords: try!(keywords_field(entry, &name)),
+		name: name,
+		description: try!(string_field(entry, "description")).unwrap_or(String::new()),
+		weight: try!(integer_field(entry, "weight", 1)),
+		value: try!(integer_field(entry, "value", 0)),
+		item_type: item_type,
+		flags: flags,
+		wear: wear,
+		modifiers: modifiers,
+	})
+}
+
+fn parse_mob(id: &str, value: &Value) -> Result<MobPrototype, String>
+{
+	let entry = try!(as_table(value, "a mob"));
+	try!(check_fields(entry, &MOB_FIELDS));
+
+	let behaviours = try!(words_field(entry, "behaviours")).unwrap_or(Vec::new());
+	for behaviour in behaviours.iter()
+	{
+		if !BEHAVIOURS.contains(&&behaviour[..])
+		{
+			return Err(format!("unknown behaviour {}, expected one of: {}", behaviour, BEHAVIOURS.join(", ")));
+		}
+	}
+
+	let name = try!(required_string(entry, "name"));
+	Ok(MobPrototype
+	{
+		id: id.to_string(),
+		keywords: try!(keywords_field(entry, &name)),
+		name: name,
+		description: try!(string_field(entry, "description")).unwrap_or(String::new()),
+		attributes: try!(attributes_field(entry, "attributes")),
+		behaviours: behaviours,
+	})
+}
+
+fn parse_reset(value: &Value) -> Result<Reset, String>
+{
+	let entry = try!(as_table(value, "a reset"));
+	try!(check_fields(entry, &RESET_FIELDS));
+
+	let kind = match &try!(required_string(entry, "kind"))[..]
+	{
+		"item" => ResetKind::Item,
+		"mob" => ResetKind::Mob,
+		other => return Err(format!("unknown reset kind {}, expected item or mob", other)),
+	};
+	let max = match entry.get("max")
+	{
+		Some(&Value::Integer(max)) if max > 0 => max as u32,
+		Some(_) => return Err("max should be a number above 0".to_string()),
+		None => 1,
+	};
+
+	Ok(Reset
+	{
+		kind: kind,
+		prototype: try!(required_string(entry, "id")),
+		room: try!(required_string(entry, "room")),
+		max: max,
+	})
+}
+
+// file and line something was defined at, for errors found after every file is read
+type Origin = (String, usize);
+
+struct AreaLoader
+{
+	world: World,
+	errors: Vec<AreaError>,
+	rooms: HashMap<String, Origin>,
+	// item and mob prototypes by id, ids are shared by every area
+	items: HashMap<String, Origin>,
+	mobs: HashMap<String, Origin>,
+	resets: Vec<Origin>,
+	start: Option<Origin>,
+	// name of the area being loaded, given to its rooms
+	area: String,
+}
+
+impl AreaLoader
+{
+	fn new() -> AreaLoader
+	{
+		AreaLoader
+		{
+			world: World::new(DEFAULT_ROOM),
+			errors: Vec::new(),
+			rooms: HashMap::new(),
+			items: HashMap::new(),
+			mobs: HashMap::new(),
+			resets: Vec::new(),
+			start: None,
+			area: String::new(),
+		}
+	}
+
+	fn error(&mut self, file: &str, line: usize, message: String)
+	{
+		self.errors.push(AreaError
+		{
+			file: file.to_string(),
+			line: line,
+			message: message,
+		});
+	}
+
+	fn load(&mut self, file: &str, source: &str)
+	{
+		let mut parser = Parser::new(source);
+		let table = match parser.parse()
+		{
+			Some(table) => table,
+			None =>
+			{
+				for e in parser.errors.iter()
+				{
+					let (line, _) = parser.to_linecol(e.lo);
+					self.error(file, line + 1, e.desc.clone());
+				}
+				return;
+			}
+		};
+
+		// rooms belong to the area named in the header, or to the file when it has none
+		self.area = table.get("area")
+			.and_then(|area| area.lookup("name"))
+			.and_then(|name| name.as_str())
+			.unwrap_or(file)
+			.to_string();
+
+		let rooms_before = self.rooms.len();
+		for (section, value) in table.iter()
+		{
+			match &section[..]
+			{
+				"area" => self.load_header(file, source, value),
+				"rooms" => self.load_rooms(file, source, value),
+				"items" | "mobs" => self.load_prototypes(file, source, section, value),
+				"resets" => self.load_resets(file, source, value),
+				_ =>
+				{
+					let line = section_line(source, section);
+					self.error(file, line, format!("unknown section {}, expected area, rooms, items, mobs or resets", section));
+				},
+			}
+		}
+		println!("Loaded area {} with {} rooms", file, self.rooms.len() - rooms_before);
+	}
+
+	fn load_header(&mut self, file: &str, source: &str, value: &Value)
+	{
+		let line = section_line(source, "area");
+		let start = as_table(value, "area")
+			.and_then(|entry| check_fields(entry, &AREA_FIELDS).map(|_| entry))
+			.and_then(|entry| string_field(entry, "start"));
+
+		match start
+		{
+			Ok(Some(start)) =>
+			{
+				if let Some((ref other, _)) = self.start
+				{
+					let message = format!("start room is already set by {}", other);
+					self.error(file, line, message);
+					return;
+				}
+				self.world.set_start_room(&start);
+				self.start = Some((file.to_string(), line));
+			},
+			Ok(None) => {},
+			Err(message) => self.error(file, line, message),
+		}
+	}
+
+	fn load_rooms(&mut self, file: &str, source: &str, value: &Value)
+	{
+		let rooms = match as_table(value, "rooms")
+		{
+			Ok(rooms) => rooms,
+			Err(message) => return self.error(file, section_line(source, "rooms"), message),
+		};
+
+		for (id, entry) in rooms.iter()
+		{
+			let line = header_line(source, "rooms", id);
+			if let Some(&(ref other, other_line)) = self.rooms.get(id)
+			{
+				let message = format!("room {} is already defined at {}:{}", id, other, other_line);
+				self.errors.push(AreaError { file: file.to_string(), line: line, message: message });
+				continue;
+			}
+
+			match parse_room(id, entry)
+			{
+				Ok(mut room) =>
+				{
+					room.area = self.area.clone();
+					self.world.add_room(room);
+					self.rooms.insert(id.clone(), (file.to_string(), line));
+				},
+				Err(message) => self.error(file, line, format!("room {}: {}", id, message)),
+			}
+		}
+	}
+
+	fn load_prototypes(&mut self, file: &str, source: &str, section: &str, value: &Value)
+	{
+		let prototypes = match as_table(value, section)
+		{
+			Ok(prototypes) => prototypes,
+			Err(message) => return self.error(file, section_line(source, section), message),
+		};
+
+		for (id, entry) in prototypes.iter()
+		{
+			let line = header_line(source, section, id);
+			let defined = if section == "items" { &self.items } else { &self.mobs };
+			if let Some(&(ref other, other_line)) = defined.get(id)
+			{
+				let kind = if section == "items" { "item" } else { "mob" };
+				let message = format!("{} {} is already defined at {}:{}", kind, id, other, other_line);
+				self.errors.push(AreaError { file: file.to_string(), line: line, message: message });
+				continue;
+			}
+
+			let result = if section == "items"
+			{
+				parse_item(id, entry).map(|item| self.world.add_item(item))
+			}
+			else
+			{
+				parse_mob(id, entry).map(|mob| self.world.add_mob(mob))
+			};
+
+			match result
+			{
+				Ok(_) =>
+				{
+					let defined = if section == "items" { &mut self.items } else { &mut self.mobs };
+					defined.insert(id.clone(), (file.to_string(), line));
+				},
+				Err(message) => self.error(file, line, format!("{} {}: {}", section, id, message)),
+			}
+		}
+	}
+
+	fn load_resets(&mut self, file: &str, source: &str, value: &Value)
+	{
+		let resets = match value.as_slice()
+		{
+			Some(resets) => resets,
+			None => return self.error(file, section_line(source, "resets"), "resets should be written as [[resets]]".to_string()),
+		};
+
+		for (index, entry) in resets.iter().enumerate()
+		{
+			let line = array_line(source, "resets", index);
+			match parse_reset(entry)
+			{
+				Ok(reset) =>
+				{
+					self.world.add_reset(reset);
+					self.resets.push((file.to_string(), line));
+				},
+				Err(message) => self.error(file, line, format!("reset: {}", message)),
+			}
+		}
+	}
+
+	// exits and resets may point into other areas, so they are checked once everything is loaded
+	fn finish(mut self) -> Result<World, Vec<AreaError>>
+	{
+		let mut errors = Vec::new();
+		for (id, &(ref file, line)) in self.rooms.iter()
+		{
+			let room = self.world.get_room(id).unwrap();
+			for (direction, to) in room.exits.iter()
+			{
+				if self.world.get_room(to).is_none()
+				{
+					let message = format!("room {}: exit {} leads to missing room {}", id, direction.name(), to);
+					errors.push(AreaError { file: file.clone(), line: line, message: message });
+				}
+			}
+		}
+
+		for (reset, &(ref file, line)) in self.world.resets().iter().zip(self.resets.iter())
+		{
+			let (kind, missing) = match reset.kind
+			{
+				ResetKind::Item => ("item", self.world.get_item(&reset.prototype).is_none()),
+				ResetKind::Mob => ("mob", self.world.get_mob(&reset.prototype).is_none()),
+			};
+			if missing
+			{
+				let message = format!("reset: there is no {} prototype {}", kind, reset.prototype);
+				errors.push(AreaError { file: file.clone(), line: line, message: message });
+			}
+			if self.world.get_room(&reset.room).is_none()
+			{
+				let message = format!("reset: there is no room {}", reset.room);
+				errors.push(AreaError { file: file.clone(), line: line, message: message });
+			}
+		}
+
+		// a file that failed to parse may hold the start room, so only check it when the rest loaded
+		if self.errors.is_empty() && self.world.get_room(self.world.start_room()).is_none()
+		{
+			let (file, line) = self.start.clone().unwrap_or((AREA_PATH.to_string(), 0));
+			let message = format!("start room {} does not exist", self.world.start_room());
+			errors.push(AreaError { file: file, line: line, message: message });
+		}
+
+		self.errors.extend(errors);
+		if self.errors.is_empty()
+		{
+			return Ok(self.world);
+		}
+
+		self.errors.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+		Err(self.errors)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	const AREA: &'static str = "[area]
+name = \"Test\"
+start = \"hall\"
+
+[rooms.hall]
+title = \"Hall\"
+description = \"A long hall.\"
+exits = { north = \"yard\" }
+
+[rooms.yard]
+title = \"Yard\"
+description = \"An empty yard.\"
+
+[rooms.yard.exits]
+south = \"hall\"
+";
+
+	#[test]
+	fn loads_rooms_and_exits()
+	{
+		let mut loader = AreaLoader::new();
+		loader.load("test.toml", AREA);
+		let world = loader.finish().ok().expect("area should load");
+		assert_eq!(world.start_room(), "hall");
+		assert_eq!(world.follow_exit("yard", Direction::South).map(|r| &r.title[..]), Some("Hall"));
+	}
+
+	#[test]
+	fn reports_dangling_exits_with_line()
+	{
+		let mut loader = AreaLoader::new();
+		loader.load("test.toml", &AREA.replace("south = \"hall\"", "south = \"cellar\""));
+		let errors = loader.finish().err().expect("missing room should fail");
+		let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+		assert_eq!(messages, vec!["test.toml:10: room yard: exit south leads to missing room cellar"]);
+	}
+
+	#[test]
+	fn reports_syntax_errors_with_line()
+	{
+		let mut loader = AreaLoader::new();
+		loader.load("bad.toml", "[rooms.hall]\ntitle = \"Hall\"\ndescription = \n");
+		let errors = loader.finish().err().expect("syntax error should fail");
+		assert_eq!(errors[0].line, 3);
+	}
+
+	#[test]
+	fn reports_duplicate_prototypes_across_files()
+	{
+		let items = "[items.ring]\nname = \"a ring\"\n\n[mobs]\nrat = { name = \"a rat\" }\n";
+		let mut loader = AreaLoader::new();
+		loader.load("a.toml", AREA);
+		loader.load("b.toml", items);
+		loader.load("c.toml", items);
+		let errors = loader.finish().err().expect("duplicates should fail");
+		let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+		assert_eq!(messages, vec!["c.toml:1: item ring is already defined at b.toml:1", "c.toml:5: mob rat is already defined at b.toml:5"]);
+	}
+
+	#[test]
+	fn finds_the_line_of_inline_rooms()
+	{
+		let source = "[area]\nname = \"Test\"\n\n[rooms]\nhall = { title = \"Hall\", description = \"\" }\n\"back room\" = { title = \"Back\" }\n";
+		assert_eq!(header_line(source, "rooms", "hall"), 5);
+		assert_eq!(header_line(source, "rooms", "back room"), 6);
+		// the section at least, for an id that can't be found
+		assert_eq!(header_line(source, "rooms", "cellar"), 4);
+		assert_eq!(header_line(AREA, "rooms", "yard"), 10);
+	}
+}
@@ -4,9 +4,23 @@ use self::rand::Rng;
 
 use std::vec;
 use std::collections::HashMap;
+use std::io::Error;
 use storage;
+use logon;
+use item::Item;
+use equipment::{self, WearSlot};
+use combat::Combatant;
+use skills;
+use skills::Learned;
+use world::World;
 use Messages::character;
 
+//columns of player_char_info that hold text, every other column is a numeric attribute
+const INFO_COLUMNS: [&'static str; 7] = ["name", "race", "gender", "type", "personality", "description", "look"];
+
+//attributes shown by score and changed by equipment
+pub const ATTRIBUTES: [&'static str; 7] = ["str", "agi", "int", "charm", "hp", "mana", "stam"];
+
 pub mod debug
 {
 	use std::collections::HashMap;
@@ -21,28 +35,190 @@ pub mod debug
 	}
 }
 
-fn save_character(character: Character)
+pub fn save_character(character: &Character)
 {
-	let mut db = storage::get_db();
-	let mut data:Vec<storage::DataColumn> = Vec::new();
+	let db = storage::get_db();
+	match storage::save(&*db, character)
+	{
+		Ok(_) => println!("Successfully saved character {}|", character.username),
+		Err(e) => println!("Failed to save character {}", e),
+	}
+}
 	
-	let info = character.info.clone();
-	let attr = character.attr.clone();
+//rebuild a saved character, None when it was never saved
+pub fn load_character(username: String) -> Option<Character>
+{
+	let db = storage::get_db();
+	match storage::load::<Character>(&*db, &username[..])
+	{
+		Ok(Some(character)) =>
+		{
+			println!("Successfully loaded character {}|", username);
+			Some(character)
+		},
+		Ok(None) => None,
+		Err(e) => { println!("Failed to load character {}: {}", username, e); None },
+	}
+}
+
+impl storage::Record for Character
+{
+	fn table() -> &'static str
+	{
+		"player_char_info"
+	}
 	
-	for (key, value) in info
+	fn key(&self) -> String
 	{
-		data.push(storage::DataColumn::new(key, value));
+		self.username.clone()
 	}
 	
-	for (key, value) in attr
+	fn to_columns(&self) -> Vec<storage::DataColumn>
 	{
-		data.push(storage::DataColumn::new(key, value.to_string()));
+		let mut data:Vec<storage::DataColumn> = Vec::new();
+		
+		for (key, value) in self.info.iter()
+		{
+			data.push(storage::DataColumn::new(key.clone(), value.clone()));
+		}
+		
+		for (key, value) in self.attr.iter()
+		{
+			data.push(storage::DataColumn::new(key.clone(), value.to_string()));
+		}
+		
+		data.push(storage::DataColumn::new("room".to_string(), self.room.clone()));
+		data.push(storage::DataColumn::new("current_hp".to_string(), self.hp.to_string()));
+		data.push(storage::DataColumn::new("current_mana".to_string(), self.mana.to_string()));
+		data.push(storage::DataColumn::new("current_stam".to_string(), self.stam.to_string()));
+		data.push(storage::DataColumn::new("practices".to_string(), self.practices.to_string()));
+		data.push(storage::DataColumn::new("level".to_string(), self.level.to_string()));
+		data.push(storage::DataColumn::new("xp".to_string(), self.xp.to_string()));
+		data.push(storage::DataColumn::new("trains".to_string(), self.trains.to_string()));
+		
+		//skills as name=proficiency pairs, cooldowns don't survive a logout
+		let skills: Vec<String> = self.skills.iter().map(|skill| format!("{}={}", skill.name, skill.proficiency)).collect();
+		data.push(storage::DataColumn::new("skills".to_string(), skills.join(",")));
+		
+		//carried items are stored as a comma separated list of prototype ids
+		let items: Vec<&str> = self.items.iter().map(|item| &item.prototype[..]).collect();
+		data.push(storage::DataColumn::new("items".to_string(), items.join(",")));
+		
+		//worn items as slot=prototype pairs
+		let equips: Vec<String> = self.equips.iter().map(|&(slot, ref item)| format!("{}={}", slot.name(), item.prototype)).collect();
+		data.push(storage::DataColumn::new("equips".to_string(), equips.join(",")));
+		
+		data
 	}
 	
-	match db.insert("player_char_info", &character.username[..], data)
+	fn from_columns(key: &str, data: Vec<storage::DataColumn>) -> Result<Character, Error>
 	{
-		Ok(_) => println!("Successfully saved character {}|", character.username),
-		Err(e) => println!("Failed to save character {}", e),
+		let mut info = HashMap::new();
+		let mut attr = HashMap::new();
+		let mut room = String::new();
+		let mut items = Vec::new();
+		let mut equips = Vec::new();
+		let mut hp = 0;
+		let mut mana = -1;
+		let mut stam = -1;
+		let mut practices = 0;
+		let mut level = 1;
+		let mut xp = 0;
+		let mut trains = 0;
+		let mut skills = Vec::new();
+		
+		for col in data
+		{
+			let column = col.get_column().to_string();
+			let value = col.get_data().to_string();
+			
+			if column == "room"
+			{
+				room = value;
+			}
+			else if column == "current_hp"
+			{
+				hp = value.trim().parse::<i32>().unwrap_or(0);
+			}
+			else if column == "current_mana"
+			{
+				mana = value.trim().parse::<i32>().unwrap_or(-1);
+			}
+			else if column == "current_stam"
+			{
+				stam = value.trim().parse::<i32>().unwrap_or(-1);
+			}
+			else if column == "practices"
+			{
+				practices = value.trim().parse::<i32>().unwrap_or(0);
+			}
+			else if column == "level"
+			{
+				level = value.trim().parse::<i32>().unwrap_or(1).max(1);
+			}
+			else if column == "xp"
+			{
+				xp = value.trim().parse::<i32>().unwrap_or(0);
+			}
+			else if column == "trains"
+			{
+				trains = value.trim().parse::<i32>().unwrap_or(0);
+			}
+			else if column == "skills"
+			{
+				for pair in value.split(',').filter(|pair| !pair.is_empty())
+				{
+					let mut parts = pair.splitn(2, '=');
+					match (parts.next().and_then(skills::find_skill), parts.next().and_then(|p| p.trim().parse::<i32>().ok()))
+					{
+						(Some(skill), Some(proficiency)) => skills.push(Learned::new(skill.name, proficiency)),
+						_ => println!("Ignoring skill {} of {}", pair, key),
+					}
+				}
+			}
+			else if column == "items"
+			{
+				items = value.split(',').filter(|id| !id.is_empty()).map(Item::new).collect();
+			}
+			else if column == "equips"
+			{
+				for pair in value.split(',').filter(|pair| !pair.is_empty())
+				{
+					let mut parts = pair.splitn(2, '=');
+					match (parts.next().and_then(WearSlot::parse), parts.next())
+					{
+						(Some(slot), Some(id)) => equips.push((slot, Item::new(id))),
+						_ => println!("Ignoring equipment {} of {}", pair, key),
+					}
+				}
+			}
+			else if INFO_COLUMNS.contains(&&column[..])
+			{
+				info.insert(column, value);
+			}
+			else
+			{
+				match value.trim().parse::<i32>()
+				{
+					Ok(num) => { attr.insert(column, num); },
+					Err(_) => println!("Ignoring attribute {} of {} with value {}", column, key, value),
+				}
+			}
+		}
+		
+		let mut character = Character::new_from_data(key.to_string(), info, attr);
+		character.room = room;
+		character.items = items;
+		character.equips = equips;
+		character.hp = hp;
+		character.mana = mana;
+		character.stam = stam;
+		character.practices = practices;
+		character.skills = skills;
+		character.level = level;
+		character.xp = xp;
+		character.trains = trains;
+		Ok(character)
 	}
 }
 	
@@ -51,9 +227,25 @@ pub struct Character
 	pub username: String,
 	pub info: HashMap<String, String>,
 	pub attr: HashMap<String, i32>,
-	pub items: Vec<()>,
-	pub equips: Vec<()>,
-	pub skills: Vec<()>,
+	// id of the room the character is in, empty until it first enters the world
+	pub room: String,
+	pub items: Vec<Item>,
+	pub equips: Vec<(WearSlot, Item)>,
+	// health left, the hp attribute is the most it can be
+	pub hp: i32,
+	// mana and stamina left, -1 until the character first enters the world
+	pub mana: i32,
+	pub stam: i32,
+	// who the character is fighting, kept between combat rounds
+	pub fighting: Option<Combatant>,
+	pub skills: Vec<Learned>,
+	// sessions left to spend on practice
+	pub practices: i32,
+	pub level: i32,
+	// experience earned over all levels
+	pub xp: i32,
+	// points left to spend on attributes
+	pub trains: i32,
 	pub history: Vec<()>,
 }
 
@@ -66,9 +258,18 @@ impl Character
 			username: String::new(),
 			info: HashMap::new(),
 			attr: HashMap::new(),
+			room: String::new(),
 			items: Vec::new(),
 			equips: Vec::new(),
+			hp: 0,
+			mana: -1,
+			stam: -1,
+			fighting: None,
 			skills: Vec::new(),
+			practices: 0,
+			level: 1,
+			xp: 0,
+			trains: 0,
 			history: Vec::new(),
 		}
 	}
@@ -80,12 +281,37 @@ impl Character
 			username: id,
 			info: char_info,
 			attr: char_attr,
+			room: String::new(),
 			items: Vec::new(),
 			equips: Vec::new(),
+			hp: 0,
+			mana: -1,
+			stam: -1,
+			fighting: None,
 			skills: Vec::new(),
+			practices: 0,
+			level: 1,
+			xp: 0,
+			trains: 0,
 			history: Vec::new(),
 		}
 	}
+	
+	//attributes that were never rolled start where initialize_attr puts them
+	pub fn base_attr(&self, name: &str) -> i32
+	{
+		match self.attr.get(name)
+		{
+			Some(value) => *value,
+			None => initialize_attr().get(name).cloned().unwrap_or(0),
+		}
+	}
+	
+	//stronger characters carry more, strength from equipment counts
+	pub fn carry_capacity(&self, world: &World) -> i32
+	{
+		50 + 10 * equipment::effective_attributes(self, world).get("str").cloned().unwrap_or(0)
+	}
 }
 
 pub fn initialize_info() -> HashMap<String, String>
@@ -376,9 +602,6 @@ impl CharCreator
 				{
 					message = character::SUCCESS.to_string();
 					creation_state = CreationState::Done;
-					//save
-					let char = Character::new_from_data(user_name.clone(), char_info.clone(), char_attributes.clone());
-					save_character(char);
 				}
 				else
 				{
@@ -392,8 +615,39 @@ impl CharCreator
 			
 		}
 		
-		let character = Character::new_from_data(user_name.clone(), char_info.clone(), char_attributes.clone());
+		let mut character = Character::new_from_data(user_name.clone(), char_info.clone(), char_attributes.clone());
+		if let CreationState::Done = creation_state
+		{
+			//save the finished character, the connection plays this same one
+			skills::learn_starting_skills(&mut character);
+			save_character(&character);
+			logon::set_stage(user_name.clone(), logon::STAGE_PLAY);
+		}
 		CharCreator::new_from_data(user_name.clone(), character, creation_state, message)
 	}
 	
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use storage::Record;
+	
+	fn prototypes(character: &Character) -> Vec<&str>
+	{
+		character.items.iter().map(|item| &item.prototype[..]).collect()
+	}
+	
+	#[test]
+	fn items_survive_the_columns()
+	{
+		let mut character = Character::new();
+		character.username = "alice".to_string();
+		assert!(prototypes(&Character::from_columns("alice", character.to_columns()).unwrap()).is_empty());
+		
+		character.items = vec![Item::new("sword"), Item::new("bread"), Item::new("bread")];
+		let loaded = Character::from_columns("alice", character.to_columns()).unwrap();
+		assert_eq!(prototypes(&loaded), vec!["sword", "bread", "bread"]);
+	}
 }
\ No newline at end of file
@@ -0,0 +1,204 @@
+// Colour markup
+// Text anywhere in the game can carry inline codes like {r or {G, every connection turns them into
+// ANSI SGR sequences or strips them, depending on what its client can show.
+//
+//   {r red      {g green    {y yellow   {b blue
+//   {m magenta  {c cyan     {w white    {d dark grey
+// Capital letters are the bright versions, {x goes back to plain text and {{ is a literal {.
+
+use commands::{Args, ArgSpec, Command, CommandOutput, CommandRegistry};
+use gamehandler::CommandContext;
+
+const RESET: &'static str = "\x1b[0m";
+
+// terminal types that understand ANSI colour, matched against the start of what TTYPE reports
+const COLOUR_TERMINALS: [&'static str; 12] = ["ANSI", "XTERM", "VT100", "VT220", "LINUX", "SCREEN", "RXVT", "PUTTY", "MUDLET", "MUSHCLIENT", "TINTIN", "ZMUD"];
+
+/// Whether a connection gets colour, and whether the player picked that themselves
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct ColourPreference
+{
+	pub enabled: bool,
+	// a choice made with the color command wins over the terminal type
+	pub chosen: bool,
+}
+
+impl ColourPreference
+{
+	pub fn new() -> ColourPreference
+	{
+		ColourPreference
+		{
+			enabled: false,
+			chosen: false,
+		}
+	}
+
+	/// The client reported its terminal type through TTYPE
+	pub fn terminal(&mut self, name: &str)
+	{
+		if !self.chosen
+		{
+			self.enabled = has_colour(name);
+		}
+	}
+
+	pub fn set(&mut self, enabled: bool)
+	{
+		self.enabled = enabled;
+		self.chosen = true;
+	}
+
+	/// Render or strip the colour codes in text to suit this connection
+	pub fn apply(&self, text: &str) -> String
+	{
+		if self.enabled { render(text) } else { strip(text) }
+	}
+}
+
+/// True when a terminal of this type can show ANSI colour
+pub fn has_colour(terminal: &str) -> bool
+{
+	let terminal = terminal.to_uppercase();
+	COLOUR_TERMINALS.iter().any(|name| terminal.starts_with(name))
+}
+
+// SGR parameters for one code letter, None when it isn't a colour code
+fn sgr(code: char) -> Option<&'static str>
+{
+	let sequence = match code
+	{
+		'x' | 'X' => RESET,
+		'r' => "\x1b[0;31m",
+		'g' => "\x1b[0;32m",
+		'y' => "\x1b[0;33m",
+		'b' => "\x1b[0;34m",
+		'm' => "\x1b[0;35m",
+		'c' => "\x1b[0;36m",
+		'w' => "\x1b[0;37m",
+		'd' => "\x1b[1;30m",
+		'R' => "\x1b[1;31m",
+		'G' => "\x1b[1;32m",
+		'Y' => "\x1b[1;33m",
+		'B' => "\x1b[1;34m",
+		'M' => "\x1b[1;35m",
+		'C' => "\x1b[1;36m",
+		'W' => "\x1b[1;37m",
+		'D' => "\x1b[1;30m",
+		_ => return None,
+	};
+	Some(sequence)
+}
+
+// walk the codes in text, handing each one to code and copying everything else
+fn convert<F: Fn(&str) -> &str>(text: &str, code: F) -> String
+{
+	let mut converted = String::with_capacity(text.len());
+	let mut coloured = false;
+	let mut chars = text.chars().peekable();
+	while let Some(c) = chars.next()
+	{
+		if c != '{'
+		{
+			converted.push(c);
+			continue;
+		}
+		match chars.peek().cloned()
+		{
+			Some('{') =>
+			{
+				chars.next();
+				converted.push('{');
+			},
+			Some(next) => match sgr(next)
+			{
+				Some(sequence) =>
+				{
+					chars.next();
+					coloured = sequence != RESET;
+					converted.push_str(code(sequence));
+				},
+				// not a code, keep it as it was typed
+				None => converted.push('{'),
+			},
+			None => converted.push('{'),
+		}
+	}
+
+	// colour never leaks into whatever comes next
+	if coloured
+	{
+		converted.push_str(code(RESET));
+	}
+	converted
+}
+
+/// Replace colour codes with ANSI sequences
+pub fn render(text: &str) -> String
+{
+	convert(text, |sequence| sequence)
+}
+
+/// Remove colour codes for clients without colour
+pub fn strip(text: &str) -> String
+{
+	convert(text, |_| "")
+}
+
+pub fn register_commands(commands: &mut CommandRegistry)
+{
+	commands.register(Command::new("color", color).alias("colour")
+		.arg(ArgSpec::word("on|off").optional())
+		.help("Show whether colour is on, or turn it on or off"));
+}
+
+fn color(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	match args.word(0).map(|word| word.to_lowercase())
+	{
+		Some(ref word) if word == "on" =>
+		{
+			context.colour.set(true);
+			CommandOutput::reply("{GColour{x is now {Con{x.\r\n".to_string())
+		},
+		Some(ref word) if word == "off" =>
+		{
+			context.colour.set(false);
+			CommandOutput::reply("Colour is now off.\r\n".to_string())
+		},
+		Some(_) => CommandOutput::reply("Usage: color on|off\r\n".to_string()),
+		None =>
+		{
+			let status = if context.colour.enabled { "on" } else { "off" };
+			CommandOutput::reply(format!("Colour is {}.\r\n", status))
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn renders_and_strips_codes()
+	{
+		let text = "{CTown square{x [Exits: {gnorth{x] {{ {q";
+		assert_eq!(render(text), "\x1b[1;36mTown square\x1b[0m [Exits: \x1b[0;32mnorth\x1b[0m] { {q");
+		assert_eq!(strip(text), "Town square [Exits: north] { {q");
+		// an open colour is closed at the end
+		assert_eq!(render("{rblood"), "\x1b[0;31mblood\x1b[0m");
+	}
+
+	#[test]
+	fn chosen_colour_outlasts_the_terminal_type()
+	{
+		let mut colour = ColourPreference::new();
+		colour.terminal("xterm-256color");
+		assert!(colour.enabled);
+		colour.set(false);
+		colour.terminal("ANSI");
+		assert!(!colour.enabled);
+		assert!(!has_colour("DUMB"));
+	}
+}
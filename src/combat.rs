@@ -0,0 +1,468 @@
+// Combat
+// Fights are kept on the characters themselves, players' and mobs' alike, so input handled
+// between two rounds never loses them. Every server tick runs one round in which everyone who is fighting swings once.
+
+extern crate rand;
+
+use self::rand::{Rng, StdRng};
+#[cfg(test)]
+use self::rand::SeedableRng;
+
+use character::Character;
+use commands::{Args, ArgSpec, Command, CommandOutput, CommandRegistry};
+use equipment;
+use experience;
+use gamehandler::CommandContext;
+use world::{capitalize, World, DIRECTIONS};
+use Messages::game;
+
+pub type CombatRng = StdRng;
+
+/// Randomness for a running server
+pub fn new_rng() -> CombatRng
+{
+	StdRng::new().ok().expect("Failed to seed the combat rng")
+}
+
+/// The same seed always plays out the same fight
+#[cfg(test)]
+pub fn seeded_rng(seed: usize) -> CombatRng
+{
+	let seed: &[usize] = &[seed];
+	StdRng::from_seed(seed)
+}
+
+/// Whoever a character is fighting
+#[derive(Clone,Debug,PartialEq)]
+pub enum Combatant
+{
+	Player(String),
+	Mob(u32),
+}
+
+/// The attributes a swing is worked out from, equipment included
+pub struct Stats
+{
+	pub strength: i32,
+	pub agility: i32,
+	pub intelligence: i32,
+	pub max_hp: i32,
+}
+
+impl Stats
+{
+	pub fn of(character: &Character, world: &World) -> Stats
+	{
+		let attributes = equipment::effective_attributes(character, world);
+		let get = |name: &str| attributes.get(name).cloned().unwrap_or(0);
+		Stats
+		{
+			strength: get("str"),
+			agility: get("agi"),
+			intelligence: get("int"),
+			max_hp: get("hp").max(1),
+		}
+	}
+}
+
+#[derive(Debug,PartialEq)]
+pub enum Swing
+{
+	Miss,
+	Hit(i32),
+	Critical(i32),
+}
+
+/// Percent chance to land a blow, agility on both sides decides it
+pub fn hit_chance(attacker: &Stats, defender: &Stats) -> i32
+{
+	(60 + 5 * (attacker.agility - defender.agility)).max(5).min(95)
+}
+
+/// One attack: agility to hit, strength for damage and intelligence to find a weak spot
+pub fn swing<R: Rng>(rng: &mut R, attacker: &Stats, defender: &Stats) -> Swing
+{
+	if rng.gen_range(0, 100) >= hit_chance(attacker, defender)
+	{
+		return Swing::Miss;
+	}
+
+	let damage = rng.gen_range(1, 5) + attacker.strength / 2;
+	let critical_chance = (attacker.intelligence * 2).max(0).min(50);
+	if rng.gen_range(0, 100) < critical_chance
+	{
+		Swing::Critical(damage.max(1) * 2)
+	}
+	else
+	{
+		Swing::Hit(damage.max(1))
+	}
+}
+
+pub fn name_of(world: &World, combatant: &Combatant) -> String
+{
+	match *combatant
+	{
+		Combatant::Player(ref name) => world.player(name).map(|c| c.username.clone()).unwrap_or(name.clone()),
+		Combatant::Mob(id) => world.population().get(id).map(|mob| mob.character.username.clone()).unwrap_or("something".to_string()),
+	}
+}
+
+pub fn character_of<'a>(world: &'a World, combatant: &Combatant) -> Option<&'a Character>
+{
+	match *combatant
+	{
+		Combatant::Player(ref name) => world.player(name),
+		Combatant::Mob(id) => world.population().get(id).map(|mob| &mob.character),
+	}
+}
+
+pub fn character_of_mut<'a>(world: &'a mut World, combatant: &Combatant) -> Option<&'a mut Character>
+{
+	match *combatant
+	{
+		Combatant::Player(ref name) => world.player_mut(name),
+		Combatant::Mob(id) => world.population_mut().get_mut(id).map(|mob| &mut mob.character),
+	}
+}
+
+/// Everyone who is fighting, in a fixed order so a seeded round always plays out the same.
+///
+/// Mobs come from the population's list of fighters, idle mobs are never looked at.
+fn fighters(world: &mut World) -> Vec<Combatant>
+{
+	let mut names: Vec<String> = world.players().filter(|c| c.fighting.is_some()).map(|c| c.username.clone()).collect();
+	names.sort();
+	let mut fighters: Vec<Combatant> = names.into_iter().map(Combatant::Player).collect();
+	fighters.extend(world.population_mut().fighting().into_iter().map(Combatant::Mob));
+	fighters
+}
+
+/// Make combatant fight back against attacker unless it is busy already
+pub fn retaliate(world: &mut World, combatant: &Combatant, attacker: &Combatant)
+{
+	match character_of_mut(world, combatant)
+	{
+		Some(character) => if character.fighting.is_none()
+		{
+			character.fighting = Some(attacker.clone());
+		},
+		None => return,
+	}
+	if let Combatant::Mob(id) = *combatant
+	{
+		world.population_mut().mark_fighting(id);
+	}
+}
+
+/// Stop everyone from fighting combatant
+fn end_fights_with(world: &mut World, combatant: &Combatant)
+{
+	for fighter in fighters(world)
+	{
+		if let Some(character) = character_of_mut(world, &fighter)
+		{
+			if character.fighting.as_ref() == Some(combatant)
+			{
+				character.fighting = None;
+			}
+		}
+	}
+}
+
+/// A dead player wakes up in the start room with full health, a dead mob is gone until its reset
+/// brings a new one
+fn die(world: &mut World, victim: &Combatant, output: &mut CommandOutput)
+{
+	end_fights_with(world, victim);
+	if let Combatant::Mob(id) = *victim
+	{
+		world.population_mut().remove(id);
+		return;
+	}
+
+	let start = world.start_room().to_string();
+	let max_hp = match character_of(world, victim)
+	{
+		Some(character) => Stats::of(character, world).max_hp,
+		None => return,
+	};
+
+	let name = name_of(world, victim);
+	if let Some(character) = character_of_mut(world, victim)
+	{
+		character.fighting = None;
+		character.hp = max_hp;
+		character.room = start.clone();
+	}
+	output.to_room(&start, format!("{} appears in a flash of light, looking shaken.\r\n", capitalize(&name)));
+	if let Combatant::Player(ref username) = *victim
+	{
+		output.to_user(username, game::YOU_DIED.to_string());
+	}
+}
+
+/// Run one round: every fighter attacks its target once
+pub fn combat_round<R: Rng>(world: &mut World, rng: &mut R) -> CommandOutput
+{
+	let mut output = CommandOutput::new();
+
+	for attacker in fighters(world)
+	{
+		// an earlier swing this round may have ended the fight
+		let (target, room) = match character_of(world, &attacker)
+		{
+			Some(character) => match character.fighting.clone()
+			{
+				Some(target) => (target, character.room.clone()),
+				None => continue,
+			},
+			None => continue,
+		};
+
+		let in_reach = character_of(world, &target).map(|c| c.room == room).unwrap_or(false);
+		if !in_reach
+		{
+			character_of_mut(world, &attacker).map(|c| c.fighting = None);
+			continue;
+		}
+
+		let attacker_stats = Stats::of(character_of(world, &attacker).unwrap(), world);
+		let defender_stats = Stats::of(character_of(world, &target).unwrap(), world);
+		let attacker_name = capitalize(&name_of(world, &attacker));
+		let target_name = name_of(world, &target);
+
+		let damage = match swing(rng, &attacker_stats, &defender_stats)
+		{
+			Swing::Miss =>
+			{
+				output.to_room(&room, format!("{} misses {}.\r\n", attacker_name, target_name));
+				0
+			},
+			Swing::Hit(damage) =>
+			{
+				output.to_room(&room, format!("{} hits {} for {} damage.\r\n", attacker_name, target_name, damage));
+				damage
+			},
+			Swing::Critical(damage) =>
+			{
+				output.to_room(&room, format!("{} lands a crushing blow on {} for {} damage!\r\n", attacker_name, target_name, damage));
+				damage
+			},
+		};
+
+		hurt(world, &attacker, &target, damage, &mut output);
+	}
+	output
+}
+
+/// Take damage off target's health, it fights back and dies when nothing is left
+pub fn hurt(world: &mut World, attacker: &Combatant, target: &Combatant, damage: i32, output: &mut CommandOutput)
+{
+	let (hp, room) = match character_of_mut(world, target)
+	{
+		Some(defender) =>
+		{
+			defender.hp -= damage;
+			(defender.hp, defender.room.clone())
+		},
+		None => return,
+	};
+	// anyone attacked fights back
+	retaliate(world, target, attacker);
+
+	if hp <= 0
+	{
+		output.to_room(&room, format!("{} is DEAD!\r\n", capitalize(&name_of(world, target))));
+		// only killing mobs is worth experience
+		let xp = match (attacker, target)
+		{
+			(&Combatant::Player(_), &Combatant::Mob(_)) => character_of(world, target).map(|mob| experience::kill_xp(Stats::of(mob, world).max_hp)),
+			_ => None,
+		};
+		die(world, target, output);
+		if let (Some(xp), &Combatant::Player(ref username)) = (xp, attacker)
+		{
+			experience::award(world, username, xp, output);
+		}
+	}
+}
+
+/// Whoever name refers to in room that username may attack, or the reason they can't
+pub fn find_target(world: &World, username: &str, room: &str, name: &str) -> Result<Combatant, &'static str>
+{
+	let target = match world.find_player(name)
+	{
+		Some(other) if other.room == room => Combatant::Player(other.username.clone()),
+		_ => match world.find_mob(room, name)
+		{
+			Some(id) => Combatant::Mob(id),
+			None => return Err(game::NOBODY_HERE),
+		},
+	};
+	if target == Combatant::Player(username.to_string())
+	{
+		return Err(game::KILL_SELF);
+	}
+	if let Combatant::Mob(id) = target
+	{
+		if is_peaceful(world, id)
+		{
+			return Err(game::PEACEFUL);
+		}
+	}
+	Ok(target)
+}
+
+/// Shopkeepers can't be attacked
+pub fn is_peaceful(world: &World, id: u32) -> bool
+{
+	world.population().get(id)
+		.and_then(|mob| world.get_mob(&mob.prototype))
+		.map(|prototype| prototype.has_behaviour("shopkeeper"))
+		.unwrap_or(false)
+}
+
+/// Start a fight between a character and target, a mob does not wait for the first blow to land
+pub fn engage(world: &mut World, username: &str, target: &Combatant)
+{
+	let me = Combatant::Player(username.to_string());
+	if let Some(character) = world.player_mut(username)
+	{
+		if character.fighting.is_none()
+		{
+			character.fighting = Some(target.clone());
+		}
+	}
+	if let Combatant::Mob(_) = *target
+	{
+		retaliate(world, target, &me);
+	}
+}
+
+pub fn register_commands(commands: &mut CommandRegistry)
+{
+	// k stays kill now that kick shares the prefix
+	commands.register(Command::new("kill", kill).alias("attack").alias("k")
+		.arg(ArgSpec::word("target"))
+		.help("Start a fight"));
+	commands.register(Command::new("flee", flee)
+		.help("Try to run from a fight"));
+}
+
+fn kill(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let name = args.word(0).unwrap_or("");
+	if context.character().fighting.is_some()
+	{
+		return CommandOutput::reply(game::ALREADY_FIGHTING.to_string());
+	}
+
+	let room = context.character().room.clone();
+	let target = match find_target(context.world, &context.username, &room, name)
+	{
+		Ok(target) => target,
+		Err(message) => return CommandOutput::reply(message.to_string()),
+	};
+
+	let target_name = name_of(context.world, &target);
+	engage(context.world, &context.username, &target);
+
+	let mut output = CommandOutput::reply(format!("You attack {}!\r\n", target_name));
+	output.to_room(&room, format!("{} attacks {}!\r\n", context.username, target_name));
+	output
+}
+
+fn flee(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	if context.character().fighting.is_none()
+	{
+		return CommandOutput::reply(game::NOT_FIGHTING.to_string());
+	}
+
+	let room = context.character().room.clone();
+	let exits: Vec<String> = DIRECTIONS.iter()
+		.filter_map(|direction| context.world.follow_exit(&room, *direction))
+		.map(|to| to.id.clone())
+		.collect();
+
+	// the more agile, the better the odds
+	let agility = equipment::effective_attributes(context.character(), context.world).get("agi").cloned().unwrap_or(0);
+	let chance = (40 + 5 * agility).min(90);
+	if exits.is_empty() || context.rng.gen_range(0, 100) >= chance
+	{
+		return CommandOutput::reply(game::FLEE_FAILED.to_string());
+	}
+
+	let to = exits[context.rng.gen_range(0, exits.len())].clone();
+	let me = Combatant::Player(context.username.clone());
+	end_fights_with(context.world, &me);
+	{
+		let character = context.character_mut();
+		character.fighting = None;
+		character.room = to.clone();
+	}
+
+	let mut output = CommandOutput::reply(game::FLED.to_string() + &context.world.describe_room(&to, &context.username));
+	output.to_room(&room, format!("{} flees!\r\n", context.username));
+	output.to_room(&to, format!("{} arrives in a panic.\r\n", context.username));
+	output
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use world::DEFAULT_ROOM;
+
+	fn fighter(name: &str, strength: i32) -> Character
+	{
+		let mut character = Character::new();
+		character.username = name.to_string();
+		character.room = DEFAULT_ROOM.to_string();
+		character.attr.insert("str".to_string(), strength);
+		character.attr.insert("agi".to_string(), 3);
+		character.attr.insert("int".to_string(), 3);
+		character.hp = character.base_attr("hp");
+		character
+	}
+
+	fn fight(seed: usize) -> Vec<String>
+	{
+		let mut world = World::default_world();
+		let mut alice = fighter("alice", 6);
+		alice.fighting = Some(Combatant::Player("bob".to_string()));
+		world.add_player(alice);
+		world.add_player(fighter("bob", 2));
+
+		let mut rng = seeded_rng(seed);
+		let mut log = Vec::new();
+		for _ in 0..100
+		{
+			let round = combat_round(&mut world, &mut rng);
+			log.extend(round.messages.into_iter().map(|(_, text)| text));
+			if world.player("alice").unwrap().fighting.is_none()
+			{
+				break;
+			}
+		}
+		log
+	}
+
+	#[test]
+	fn same_seed_same_fight()
+	{
+		let first = fight(7);
+		assert_eq!(first, fight(7));
+		assert!(first.iter().any(|line| line == "Bob is DEAD!\r\n"));
+	}
+
+	#[test]
+	fn hit_chance_is_bounded()
+	{
+		let quick = Stats { strength: 0, agility: 50, intelligence: 0, max_hp: 1 };
+		let slow = Stats { strength: 0, agility: 0, intelligence: 0, max_hp: 1 };
+		assert_eq!(hit_chance(&quick, &slow), 95);
+		assert_eq!(hit_chance(&slow, &quick), 5);
+	}
+}
@@ -0,0 +1,560 @@
+// Command parsing and dispatch
+// Input is split into a verb and arguments, the verb is resolved against the registered
+// commands and the arguments are checked against the command's argument list.
+
+use messagebus::Target;
+use gamehandler::CommandContext;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ArgKind
+{
+	// a single word, quotes group several words into one
+	Word,
+	// a whole number
+	Number,
+	// everything left on the line
+	Text,
+}
+
+#[derive(Copy,Clone,Debug)]
+pub struct ArgSpec
+{
+	pub name: &'static str,
+	pub kind: ArgKind,
+	pub required: bool,
+}
+
+impl ArgSpec
+{
+	pub fn word(name: &'static str) -> ArgSpec
+	{
+		ArgSpec { name: name, kind: ArgKind::Word, required: true }
+	}
+
+	pub fn number(name: &'static str) -> ArgSpec
+	{
+		ArgSpec { name: name, kind: ArgKind::Number, required: true }
+	}
+
+	pub fn text(name: &'static str) -> ArgSpec
+	{
+		ArgSpec { name: name, kind: ArgKind::Text, required: true }
+	}
+
+	pub fn optional(mut self) -> ArgSpec
+	{
+		self.required = false;
+		self
+	}
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Arg
+{
+	Word(String),
+	Number(i32),
+	Text(String),
+}
+
+/// Parsed arguments, in the order of the command's `ArgSpec`s
+pub struct Args
+{
+	// name of the command that was resolved, aliases included
+	pub command: &'static str,
+	values: Vec<Option<Arg>>,
+}
+
+impl Args
+{
+	pub fn word(&self, index: usize) -> Option<&str>
+	{
+		match self.values.get(index)
+		{
+			Some(&Some(Arg::Word(ref word))) => Some(word),
+			_ => None,
+		}
+	}
+
+	pub fn number(&self, index: usize) -> Option<i32>
+	{
+		match self.values.get(index)
+		{
+			Some(&Some(Arg::Number(number))) => Some(number),
+			_ => None,
+		}
+	}
+
+	pub fn text(&self, index: usize) -> Option<&str>
+	{
+		match self.values.get(index)
+		{
+			Some(&Some(Arg::Text(ref text))) => Some(text),
+			_ => None,
+		}
+	}
+}
+
+/// What a command sends back to the player and publishes to everyone else
+pub struct CommandOutput
+{
+	// shown to the player who typed the command
+	pub reply: String,
+	// published on the message bus
+	pub messages: Vec<(Target, String)>,
+}
+
+impl CommandOutput
+{
+	pub fn new() -> CommandOutput
+	{
+		CommandOutput
+		{
+			reply: String::new(),
+			messages: Vec::new(),
+		}
+	}
+
+	pub fn reply(text: String) -> CommandOutput
+	{
+		let mut output = CommandOutput::new();
+		output.reply = text;
+		output
+	}
+
+	pub fn to_room(&mut self, room: &str, text: String)
+	{
+		self.messages.push((Target::Room(room.to_string()), text));
+	}
+
+	pub fn to_all(&mut self, text: String)
+	{
+		self.messages.push((Target::All, text));
+	}
+
+	pub fn to_user(&mut self, user: &str, text: String)
+	{
+		self.messages.push((Target::User(user.to_string()), text));
+	}
+
+	pub fn to_channel(&mut self, channel: &str, text: String)
+	{
+		self.messages.push((Target::Channel(channel.to_string()), text));
+	}
+
+	/// Add the reply and messages of another output after this one's
+	pub fn append(&mut self, other: CommandOutput)
+	{
+		self.reply = self.reply.clone() + &other.reply;
+		self.messages.extend(other.messages);
+	}
+}
+
+pub type Handler = fn(&mut CommandContext, &Args) -> CommandOutput;
+
+pub struct Command
+{
+	pub name: &'static str,
+	pub aliases: Vec<&'static str>,
+	pub args: Vec<ArgSpec>,
+	pub help: &'static str,
+	pub handler: Handler,
+	// only admins can see and use it
+	pub admin: bool,
+}
+
+impl Command
+{
+	pub fn new(name: &'static str, handler: Handler) -> Command
+	{
+		Command
+		{
+			name: name,
+			aliases: Vec::new(),
+			args: Vec::new(),
+			help: "",
+			handler: handler,
+			admin: false,
+		}
+	}
+
+	pub fn alias(mut self, alias: &'static str) -> Command
+	{
+		self.aliases.push(alias);
+		self
+	}
+
+	pub fn arg(mut self, spec: ArgSpec) -> Command
+	{
+		self.args.push(spec);
+		self
+	}
+
+	pub fn help(mut self, help: &'static str) -> Command
+	{
+		self.help = help;
+		self
+	}
+
+	pub fn admin(mut self) -> Command
+	{
+		self.admin = true;
+		self
+	}
+
+	pub fn usage(&self) -> String
+	{
+		let mut usage = self.name.to_string();
+		for spec in self.args.iter()
+		{
+			if spec.required
+			{
+				usage = usage + " <" + spec.name + ">";
+			}
+			else
+			{
+				usage = usage + " [" + spec.name + "]";
+			}
+		}
+		usage
+	}
+}
+
+pub enum Resolution<'a>
+{
+	Found(&'a Command),
+	Ambiguous(Vec<&'static str>),
+	Unknown(Vec<&'static str>),
+}
+
+/// Split input into words, double quotes keep spaces inside one word.
+///
+/// A leading punctuation alias such as ' is split off as its own word.
+pub fn tokenize(input: &str) -> Vec<String>
+{
+	let mut words = Vec::new();
+	let mut current = String::new();
+	let mut quoted = false;
+
+	let input = input.trim();
+	let mut chars = input.chars().peekable();
+
+	if let Some(&first) = chars.peek()
+	{
+		if !first.is_alphanumeric() && first != '"'
+		{
+			words.push(first.to_string());
+			chars.next();
+		}
+	}
+
+	for c in chars
+	{
+		if c == '"'
+		{
+			quoted = !quoted;
+		}
+		else if c.is_whitespace() && !quoted
+		{
+			if !current.is_empty()
+			{
+				words.push(current.clone());
+				current.clear();
+			}
+		}
+		else
+		{
+			current.push(c);
+		}
+	}
+
+	if !current.is_empty()
+	{
+		words.push(current);
+	}
+	words
+}
+
+// the text after the first n words, taken from the original input so spacing is kept
+fn remainder(input: &str, words: usize) -> String
+{
+	let input = input.trim();
+	let mut chars = input.char_indices().peekable();
+	let mut skipped = 0;
+
+	if let Some(&(_, first)) = chars.peek()
+	{
+		if !first.is_alphanumeric() && first != '"'
+		{
+			chars.next();
+			skipped += 1;
+		}
+	}
+
+	let mut quoted = false;
+	let mut in_word = false;
+	for (index, c) in chars
+	{
+		if skipped >= words && !c.is_whitespace()
+		{
+			return input[index..].to_string();
+		}
+
+		if c == '"'
+		{
+			quoted = !quoted;
+			in_word = true;
+		}
+		else if c.is_whitespace() && !quoted
+		{
+			if in_word
+			{
+				skipped += 1;
+				in_word = false;
+			}
+		}
+		else
+		{
+			in_word = true;
+		}
+	}
+	String::new()
+}
+
+// Levenshtein distance between two words
+fn edit_distance(a: &str, b: &str) -> usize
+{
+	let b_chars: Vec<char> = b.chars().collect();
+	let mut previous: Vec<usize> = (0..b_chars.len() + 1).collect();
+
+	for (i, ca) in a.chars().enumerate()
+	{
+		let mut current = vec![i + 1];
+		for (j, cb) in b_chars.iter().enumerate()
+		{
+			let cost = if ca == *cb { 0 } else { 1 };
+			let value = *[previous[j + 1] + 1, current[j] + 1, previous[j] + cost].iter().min().unwrap();
+			current.push(value);
+		}
+		previous = current;
+	}
+	previous[b_chars.len()]
+}
+
+pub struct CommandRegistry
+{
+	commands: Vec<Command>,
+}
+
+impl CommandRegistry
+{
+	pub fn new() -> CommandRegistry
+	{
+		CommandRegistry
+		{
+			commands: Vec::new(),
+		}
+	}
+
+	pub fn register(&mut self, command: Command)
+	{
+		self.commands.push(command);
+	}
+
+	pub fn commands(&self) -> &Vec<Command>
+	{
+		&self.commands
+	}
+
+	/// Find a command by name or alias, then by a prefix that only one command name starts with.
+	///
+	/// Admin commands are left out unless admin is set, so they never shadow a player's prefix.
+	pub fn resolve<'a>(&'a self, verb: &str, admin: bool) -> Resolution<'a>
+	{
+		let verb = verb.to_lowercase();
+		let visible: Vec<&Command> = self.commands.iter().filter(|c| admin || !c.admin).collect();
+
+		for &command in visible.iter()
+		{
+			if command.name == verb || command.aliases.contains(&&verb[..])
+			{
+				return Resolution::Found(command);
+			}
+		}
+
+		let matches: Vec<&Command> = visible.iter().cloned().filter(|c| c.name.starts_with(&verb[..])).collect();
+		if matches.len() == 1
+		{
+			return Resolution::Found(matches[0]);
+		}
+		if matches.len() > 1
+		{
+			return Resolution::Ambiguous(matches.iter().map(|c| c.name).collect());
+		}
+
+		// nothing matched, suggest the names that are a typo or two away
+		let mut suggestions: Vec<(usize, &'static str)> = Vec::new();
+		for command in visible
+		{
+			let distance = edit_distance(&verb, command.name);
+			if distance <= 2 && distance < command.name.len()
+			{
+				suggestions.push((distance, command.name));
+			}
+		}
+		suggestions.sort();
+		Resolution::Unknown(suggestions.iter().map(|s| s.1).collect())
+	}
+
+	fn parse_args(command: &Command, input: &str, words: &Vec<String>) -> Result<Args, String>
+	{
+		let mut values = Vec::new();
+		let mut next = 1;
+
+		for spec in command.args.iter()
+		{
+			let value = match spec.kind
+			{
+				ArgKind::Text =>
+				{
+					let text = remainder(input, next);
+					next = words.len();
+					if text.is_empty() { None } else { Some(Arg::Text(text)) }
+				},
+				ArgKind::Word =>
+				{
+					let word = words.get(next).cloned();
+					next += 1;
+					word.map(Arg::Word)
+				},
+				ArgKind::Number =>
+				{
+					let word = words.get(next).cloned();
+					next += 1;
+					match word
+					{
+						Some(word) => match word.parse::<i32>()
+						{
+							Ok(number) => Some(Arg::Number(number)),
+							Err(_) => return Err(format!("{} should be a number\r\n", spec.name)),
+						},
+						None => None,
+					}
+				},
+			};
+
+			if value.is_none() && spec.required
+			{
+				return Err(format!("Usage: {}\r\n", command.usage()));
+			}
+			values.push(value);
+		}
+
+		Ok(Args
+		{
+			command: command.name,
+			values: values,
+		})
+	}
+
+	/// Resolve the verb of the input and run its handler
+	pub fn dispatch(&self, context: &mut CommandContext, input: &str) -> CommandOutput
+	{
+		let words = tokenize(input);
+		if words.is_empty()
+		{
+			return CommandOutput::new();
+		}
+
+		match self.resolve(&words[0], context.admin)
+		{
+			Resolution::Found(command) =>
+			{
+				match CommandRegistry::parse_args(command, input, &words)
+				{
+					Ok(args) => (command.handler)(context, &args),
+					Err(usage) => CommandOutput::reply(usage),
+				}
+			},
+			Resolution::Ambiguous(names) =>
+			{
+				CommandOutput::reply(format!("'{}' could mean: {}\r\n", words[0], names.join(", ")))
+			},
+			Resolution::Unknown(suggestions) =>
+			{
+				if suggestions.is_empty()
+				{
+					CommandOutput::reply(format!("Huh? '{}' is not a command, type 'help' for a list\r\n", words[0]))
+				}
+				else
+				{
+					CommandOutput::reply(format!("Huh? '{}' is not a command, did you mean: {}?\r\n", words[0], suggestions.join(", ")))
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn noop(_: &mut CommandContext, _: &Args) -> CommandOutput
+	{
+		CommandOutput::new()
+	}
+
+	fn registry() -> CommandRegistry
+	{
+		let mut registry = CommandRegistry::new();
+		registry.register(Command::new("say", noop).alias("'").arg(ArgSpec::text("message")));
+		registry.register(Command::new("shout", noop).arg(ArgSpec::text("message")));
+		registry.register(Command::new("look", noop).alias("l").arg(ArgSpec::text("target").optional()));
+		registry.register(Command::new("shutdown", noop).admin());
+		registry
+	}
+
+	fn resolved_name(registry: &CommandRegistry, verb: &str) -> Option<&'static str>
+	{
+		match registry.resolve(verb, false)
+		{
+			Resolution::Found(command) => Some(command.name),
+			_ => None,
+		}
+	}
+
+	#[test]
+	fn resolves_aliases_and_prefixes()
+	{
+		let registry = registry();
+		assert_eq!(resolved_name(&registry, "'"), Some("say"));
+		assert_eq!(resolved_name(&registry, "l"), Some("look"));
+		assert_eq!(resolved_name(&registry, "sh"), Some("shout"));
+		assert_eq!(resolved_name(&registry, "s"), None);
+		assert_eq!(resolved_name(&registry, "shutdown"), None);
+		match registry.resolve("shut", true)
+		{
+			Resolution::Found(command) => assert_eq!(command.name, "shutdown"),
+			_ => panic!("admins should find shutdown"),
+		}
+	}
+
+	#[test]
+	fn suggests_close_commands()
+	{
+		match registry().resolve("lok", false)
+		{
+			Resolution::Unknown(suggestions) => assert_eq!(suggestions, vec!["look"]),
+			_ => panic!("lok should not resolve"),
+		}
+	}
+
+	#[test]
+	fn splits_words_and_keeps_text()
+	{
+		assert_eq!(tokenize("'hello  there"), vec!["'", "hello", "there"]);
+		assert_eq!(tokenize("tell \"big bob\" hi"), vec!["tell", "big bob", "hi"]);
+		assert_eq!(remainder("'hello  there", 1), "hello  there");
+		assert_eq!(remainder("tell bob  hi there", 2), "hi there");
+	}
+}
@@ -0,0 +1,314 @@
+// Server configuration
+// Defaults, overridden by the config file, overridden by the command line. Every setting has
+// the same name in both places, e.g. tick_ms = 500 in the file or --tick-ms 500 on the command
+// line.
+
+extern crate toml;
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use self::toml::{Parser, Value};
+
+use area;
+use storage;
+
+pub const CONFIG_PATH: &'static str = "./server.toml";
+
+const BACKENDS: [&'static str; 3] = ["file", "sqlite", "memory"];
+
+pub struct Config
+{
+	// address the listening socket binds to
+	pub bind: String,
+	// file the transaction log keeps delivered messages in
+	pub log_db: String,
+	// where players and characters are stored, see storage::open_storage
+	pub storage: String,
+	pub storage_path: String,
+	// directory of the area files
+	pub areas: String,
+	// connections the slab has room for at first, it grows up to max_connections
+	pub slab_size: usize,
+	// connections past this are turned away with a message
+	pub max_connections: usize,
+	// most connections at once from one IP address
+	pub max_per_ip: usize,
+	// usernames allowed to use admin commands, exactly as they log on
+	pub admins: Vec<String>,
+	// time between two server ticks
+	pub tick_ms: u64,
+	// token the tick timer is registered with
+	pub timer_token: u32,
+	// seconds without input before a connection is dropped, while logging on, while creating a
+	// character and while playing
+	pub logon_timeout: u64,
+	pub creation_timeout: u64,
+	pub play_timeout: u64,
+	// seconds the character of a dropped connection waits in the world for its player to return
+	pub linkdead_timeout: u64,
+}
+
+impl Config
+{
+	pub fn new() -> Config
+	{
+		Config
+		{
+			bind: "127.0.0.1:8000".to_string(),
+			log_db: "db/messages.db".to_string(),
+			storage: "file".to_string(),
+			storage_path: storage::DEFAULT_PATH.to_string(),
+			areas: area::AREA_PATH.to_string(),
+			slab_size: 128,
+			max_connections: 1024,
+			max_per_ip: 8,
+			admins: Vec::new(),
+			tick_ms: 1000,
+			timer_token: 123,
+			logon_timeout: 60,
+			creation_timeout: 300,
+			play_timeout: 1800,
+			linkdead_timeout: 300,
+		}
+	}
+
+	/// Change one setting from its text form
+	pub fn set(&mut self, key: &str, value: &str) -> Result<(), String>
+	{
+		match key
+		{
+			"bind" => self.bind = value.to_string(),
+			"log_db" => self.log_db = value.to_string(),
+			"storage" => self.storage = value.to_string(),
+			"storage_path" => self.storage_path = value.to_string(),
+			"areas" => self.areas = value.to_string(),
+			"slab_size" => self.slab_size = try!(number(key, value)),
+			"max_connections" => self.max_connections = try!(number(key, value)),
+			"max_per_ip" => self.max_per_ip = try!(number(key, value)),
+			// a comma separated list
+			"admins" => self.admins = value.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect(),
+			"tick_ms" => self.tick_ms = try!(number(key, value)),
+			"timer_token" => self.timer_token = try!(number(key, value)),
+			"logon_timeout" => self.logon_timeout = try!(number(key, value)),
+			"creation_timeout" => self.creation_timeout = try!(number(key, value)),
+			"play_timeout" => self.play_timeout = try!(number(key, value)),
+			"linkdead_timeout" => self.linkdead_timeout = try!(number(key, value)),
+			_ => return Err(format!("unknown setting {}", key)),
+		}
+		Ok(())
+	}
+
+	/// Read the settings of a TOML config file on top of these
+	pub fn load_file(&mut self, path: &str) -> Result<(), Vec<String>>
+	{
+		let mut source = String::new();
+		if let Err(e) = File::open(path).and_then(|mut file| file.read_to_string(&mut source))
+		{
+			return Err(vec![format!("{}: {}", path, e)]);
+		}
+
+		let mut parser = Parser::new(&source);
+		let table = match parser.parse()
+		{
+			Some(table) => table,
+			None =>
+			{
+				return Err(parser.errors.iter().map(|e|
+				{
+					let (line, _) = parser.to_linecol(e.lo);
+					format!("{}:{}: {}", path, line + 1, e.desc)
+				}).collect());
+			}
+		};
+
+		let mut errors = Vec::new();
+		for (key, value) in table.iter()
+		{
+			let text = match *value
+			{
+				Value::String(ref text) => text.clone(),
+				Value::Integer(number) => number.to_string(),
+				Value::Array(ref values) if values.iter().all(|value| value.as_str().is_some()) =>
+				{
+					values.iter().filter_map(|value| value.as_str()).collect::<Vec<&str>>().join(",")
+				},
+				ref other =>
+				{
+					errors.push(format!("{}: {} should be a string or a number, not {}", path, key, other.type_str()));
+					continue;
+				},
+			};
+			if let Err(message) = self.set(key, &text)
+			{
+				errors.push(format!("{}: {}", path, message));
+			}
+		}
+		if errors.is_empty() { Ok(()) } else { Err(errors) }
+	}
+
+	/// Every problem with the settings, empty when the server can start with them
+	pub fn validate(&self) -> Vec<String>
+	{
+		let mut errors = Vec::new();
+		if SocketAddr::from_str(&self.bind).is_err()
+		{
+			errors.push(format!("bind {} is not an address like 127.0.0.1:8000", self.bind));
+		}
+		if !BACKENDS.contains(&&self.storage[..])
+		{
+			errors.push(format!("unknown storage {}, expected one of: {}", self.storage, BACKENDS.join(", ")));
+		}
+		for &(key, value) in [("log_db", &self.log_db), ("storage_path", &self.storage_path), ("areas", &self.areas)].iter()
+		{
+			if value.is_empty()
+			{
+				errors.push(format!("{} can't be empty", key));
+			}
+		}
+		if self.slab_size < 1
+		{
+			errors.push("slab_size should be at least 1".to_string());
+		}
+		if self.slab_size > self.max_connections
+		{
+			errors.push(format!("slab_size {} is larger than max_connections {}", self.slab_size, self.max_connections));
+		}
+		if self.max_per_ip < 1
+		{
+			errors.push("max_per_ip should be at least 1".to_string());
+		}
+		if self.tick_ms < 10
+		{
+			errors.push(format!("tick_ms {} is too short, use at least 10", self.tick_ms));
+		}
+		for &(key, value) in [("logon_timeout", self.logon_timeout), ("creation_timeout", self.creation_timeout), ("play_timeout", self.play_timeout)].iter()
+		{
+			if value < 1
+			{
+				errors.push(format!("{} should be at least 1 second", key));
+			}
+		}
+		errors
+	}
+}
+
+impl fmt::Display for Config
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		try!(writeln!(f, "  bind             = {}", self.bind));
+		try!(writeln!(f, "  log_db           = {}", self.log_db));
+		try!(writeln!(f, "  storage          = {}", self.storage));
+		try!(writeln!(f, "  storage_path     = {}", self.storage_path));
+		try!(writeln!(f, "  areas            = {}", self.areas));
+		try!(writeln!(f, "  slab_size        = {}", self.slab_size));
+		try!(writeln!(f, "  max_connections  = {}", self.max_connections));
+		try!(writeln!(f, "  max_per_ip       = {}", self.max_per_ip));
+		try!(writeln!(f, "  admins           = {}", self.admins.join(",")));
+		try!(writeln!(f, "  tick_ms          = {}", self.tick_ms));
+		try!(writeln!(f, "  timer_token      = {}", self.timer_token));
+		try!(writeln!(f, "  logon_timeout    = {}", self.logon_timeout));
+		try!(writeln!(f, "  creation_timeout = {}", self.creation_timeout));
+		try!(writeln!(f, "  play_timeout     = {}", self.play_timeout));
+		write!(f, "  linkdead_timeout = {}", self.linkdead_timeout)
+	}
+}
+
+fn number<T: FromStr>(key: &str, value: &str) -> Result<T, String>
+{
+	value.trim().parse().map_err(|_| format!("{} should be a positive number, not {}", key, value))
+}
+
+/// Build the configuration from the command line arguments, without the program name.
+///
+/// --config picks the file, which then has to exist; without it ./server.toml is read if it is
+/// there. Every other --some-setting value pair overrides the file.
+pub fn from_args(args: &[String]) -> Result<Config, Vec<String>>
+{
+	let mut overrides = Vec::new();
+	let mut path = None;
+	let mut errors = Vec::new();
+
+	let mut args = args.iter();
+	while let Some(arg) = args.next()
+	{
+		if !arg.starts_with("--")
+		{
+			errors.push(format!("unexpected argument {}", arg));
+			continue;
+		}
+		let key = arg[2..].replace("-", "_");
+		match args.next()
+		{
+			Some(value) if key == "config" => path = Some(value.clone()),
+			Some(value) => overrides.push((key, value.clone())),
+			None => errors.push(format!("{} needs a value", arg)),
+		}
+	}
+
+	let mut config = Config::new();
+	let file = match path
+	{
+		Some(path) => Some(path),
+		None if File::open(CONFIG_PATH).is_ok() => Some(CONFIG_PATH.to_string()),
+		None => None,
+	};
+	if let Some(file) = file
+	{
+		if let Err(file_errors) = config.load_file(&file)
+		{
+			errors.extend(file_errors);
+		}
+	}
+
+	for (key, value) in overrides
+	{
+		if let Err(message) = config.set(&key, &value)
+		{
+			errors.push(message);
+		}
+	}
+
+	errors.extend(config.validate());
+	if errors.is_empty() { Ok(config) } else { Err(errors) }
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn args(line: &str) -> Vec<String>
+	{
+		line.split_whitespace().map(|arg| arg.to_string()).collect()
+	}
+
+	#[test]
+	fn command_line_overrides_defaults()
+	{
+		let config = from_args(&args("--bind 0.0.0.0:4000 --tick-ms 250 --storage memory --admins Alice,,bob --logon-timeout 30")).ok().unwrap();
+		assert_eq!(config.bind, "0.0.0.0:4000");
+		assert_eq!(config.tick_ms, 250);
+		assert_eq!(config.storage, "memory");
+		assert_eq!(config.slab_size, 128);
+		assert_eq!(config.logon_timeout, 30);
+		assert_eq!(config.play_timeout, 1800);
+		assert_eq!(config.admins, vec!["Alice".to_string(), "bob".to_string()]);
+	}
+
+	#[test]
+	fn reports_bad_settings()
+	{
+		let errors = from_args(&args("--bind nowhere --tick-ms fast --colour red --slab-size")).err().unwrap();
+		assert_eq!(errors, vec![
+			"--slab-size needs a value".to_string(),
+			"tick_ms should be a positive number, not fast".to_string(),
+			"unknown setting colour".to_string(),
+			"bind nowhere is not an address like 127.0.0.1:8000".to_string(),
+		]);
+	}
+}
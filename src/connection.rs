@@ -9,25 +9,47 @@ use character;
 use character::CharCreator;
 use character::CreationState;
 
-use transactionstorage;
-use transactionstorage::SqliteDB;
-use transactionstorage::Transaction;
+use messagebus::{Envelope, MessageBus, Target};
 
 use gamehandler;
 use gamehandler::GameHandler;
 
+use telnet;
+
+use colour::ColourPreference;
+
+use combat::CombatRng;
+
+use linebuffer;
+use linebuffer::{Line, LineBuffer};
+
+use outbuffer;
+use outbuffer::OutBuffer;
+
+use limits::IdleTimeouts;
+
 use std::io;
+use std::mem;
 use std::io::{Error, ErrorKind};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
 use Messages::greeting;
+use Messages::character as character_messages;
+use Messages::connection;
 
 use mio::*;
 use mio::tcp::*;
 
 use server;
 
+#[cfg(unix)]
+use copyover::SavedConnection;
+
 
-enum ConnectionState
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ConnectionState
 {
 	New,
 	Logon,
@@ -35,20 +57,6 @@ enum ConnectionState
 	Play,
 }
 
-fn get_input_string(message: &ByteBuf) -> String
-{
-	let input_bytes = message.bytes();
-	let mut u8_buf = Vec::new();
-	
-	for index in 0..input_bytes.len()
-	{
-		u8_buf.push(input_bytes[index]);
-	}
-	
-	let input_string = String::from_utf8(u8_buf).unwrap();
-	input_string
-}
-
 /// A stateful wrapper around a non-blocking stream. This connection is not
 /// the SERVER connection. This connection represents the client connections
 /// _accepted_ by the SERVER connection.
@@ -58,29 +66,53 @@ pub struct Connection {
 
     // token used to register with the event loop
     pub token: Token,
-	
-	// Last updated time
-	pub lastUpdate: time::Timespec,
-	
-	dbclient: transactionstorage::SqliteDB,
+
+	// where the client connected from
+	addr: SocketAddr,
 
     // set of events we are interested in
     interest: EventSet,
 
-    // messages waiting to be sent out
-    send_queue: Vec<ByteBuf>,
+    // bytes waiting to be sent out, in the order they were queued
+    output: OutBuffer,
 	
 	state: ConnectionState,
 	
 	logon_handler: logon::LogonManager,
 	character_creator: character::CharCreator,
+	
+	// telnet option negotiation state for this client
+	telnet: telnet::TelnetCodec,
+	
+	// whether colour codes are rendered or stripped for this client
+	colour: ColourPreference,
+	
+	// input that has not been terminated by a newline yet
+	input_buffer: LineBuffer,
+	
+	// set once the connection should be dropped after its queue is sent
+	closing: bool,
+	
+	// set when the client stopped reading and its output went past the high-water mark
+	stalled: bool,
+	
+	// set when this connection took the character over from another one of the same player,
+	// the server then moves the rest of that session here
+	took_over: bool,
+	
+	// when the client last sent a complete line
+	last_input: Instant,
+	
+	// channels the player listens to
+	channels: Vec<String>,
 }
 
 impl Connection {
-    pub fn new(sock: TcpStream, token: Token, db:transactionstorage::SqliteDB) -> Connection {
+    pub fn new(sock: TcpStream, token: Token, addr: SocketAddr) -> Connection {
         Connection {
             sock: sock,
             token: token,
+			addr: addr,
 
             // new connections are only listening for a hang up event when
             // they are first created. we always want to make sure we are 
@@ -88,93 +120,385 @@ impl Connection {
             // for readable and writable events later on.
             interest: EventSet::hup(),			
 
-            send_queue: Vec::new(),
-			
-			dbclient: db,
+            output: OutBuffer::new(outbuffer::HIGH_WATER_MARK),
 			
 			state: ConnectionState::Logon,
 			
-			lastUpdate: time::get_time(),
-			
 			logon_handler: logon::LogonManager::new(),
 			character_creator: character::CharCreator::new(),			
+			
+			telnet: telnet::TelnetCodec::new(),
+			
+			colour: ColourPreference::new(),
+			
+			input_buffer: LineBuffer::new(linebuffer::MAX_LINE_LENGTH),
+			
+			closing: false,
+			
+			stalled: false,
+			
+			took_over: false,
+			
+			last_input: Instant::now(),
+			
+			channels: gamehandler::DEFAULT_CHANNELS.iter().map(|c| c.to_string()).collect(),
         }
     }	
 	
-	pub fn set_last_update(&mut self, updatedTime: time::Timespec)
+	/// Whether a message sent to target should reach this connection
+	pub fn is_subscribed(&self, target: &Target, game: &GameHandler) -> bool
 	{
-		self.lastUpdate = updatedTime.clone();
+		let room = game.world().player(self.username()).map(|c| &c.room[..]);
+		target.reaches(self.username(), room, &self.channels)
 	}
 	
-	pub fn get_last_update(self) -> time::Timespec
+	pub fn username(&self) -> &str
 	{
-		self.lastUpdate
+		&self.logon_handler.username
 	}
 	
-	pub fn handle_heartbeat(&mut self)
+	/// Address of the client, per-IP connection limits count these
+	pub fn ip(&self) -> IpAddr
 	{
-		match self.state
+		self.addr.ip()
+	}
+	
+	/// Stop taking input and drop the connection once the queued messages are sent
+	pub fn close(&mut self)
+	{
+		self.closing = true;
+	}
+	
+	/// True once the connection was asked to close, rather than dropped by the client
+	pub fn is_closing(&self) -> bool
+	{
+		self.closing
+	}
+	
+	/// True when the client sent nothing for longer than its state allows
+	pub fn is_idle(&self, timeouts: &IdleTimeouts, now: Instant) -> bool
+	{
+		timeouts.is_idle(self.state, self.last_input, now)
+	}
+	
+	/// True once after this connection took over another session of its player
+	pub fn took_over(&mut self) -> bool
+	{
+		mem::replace(&mut self.took_over, false)
+	}
+	
+	/// Give the character up to a newer connection of the same player and disconnect.
+	///
+	/// Returns the output that was not sent yet and the channels, they move to the new connection.
+	pub fn hand_over(&mut self) -> (Vec<u8>, Vec<String>)
+	{
+		let output = self.output.take();
+		// the character is no longer this connection's to save or take out of the world
+		self.state = ConnectionState::Logon;
+		self.send(connection::TAKEN_OVER.to_string());
+		self.close();
+		(output, mem::replace(&mut self.channels, Vec::new()))
+	}
+	
+	/// Continue the session another connection handed over
+	pub fn take_over(&mut self, output: Vec<u8>, channels: Vec<String>)
+	{
+		if !output.is_empty()
 		{
-			ConnectionState::Play =>
+			self.send_message(ByteBuf::from_slice(&output)).ok();
+		}
+		self.channels = channels;
+	}
+	
+	/// True while there is output waiting to be written
+	pub fn has_output(&self) -> bool
+	{
+		!self.output.is_empty()
+	}
+	
+	/// True when a closing connection has nothing left to send, or can't send it anyway
+	pub fn is_closed(&self) -> bool
+	{
+		self.closing && (self.output.is_empty() || self.stalled)
+	}
+	
+	/// Everything a copyover needs to bring this connection back in the new process
+	#[cfg(unix)]
+	pub fn save(&self) -> SavedConnection
+	{
+		let state = match self.state
+		{
+			ConnectionState::New | ConnectionState::Logon => "logon",
+			ConnectionState::CharacterCreation => "creation",
+			ConnectionState::Play => "play",
+		};
+		// a logon half way through registering starts over, the typed password isn't written out
+		let logon_state = match self.logon_handler.logon_state
+		{
+			LogonState::Password => "password",
+			_ => "username",
+		};
+		SavedConnection
+		{
+			fd: self.sock.as_raw_fd(),
+			token: self.token.as_usize(),
+			state: state.to_string(),
+			username: self.logon_handler.username.clone(),
+			logon_state: logon_state.to_string(),
+			failed_attempts: self.logon_handler.failed_attempts,
+			channels: self.channels.clone(),
+			colour: self.colour,
+			telnet: self.telnet_options().clone(),
+		}
+	}
+	
+	/// Pick up a session saved before a copyover, players go straight back into the world
+	#[cfg(unix)]
+	pub fn restore(sock: TcpStream, token: Token, addr: SocketAddr, saved: SavedConnection, game: &mut GameHandler) -> Connection
+	{
+		let mut conn = Connection::new(sock, token, addr);
+		conn.telnet = telnet::TelnetCodec::with_options(saved.telnet);
+		conn.colour = saved.colour;
+		conn.channels = saved.channels;
+		conn.logon_handler.username = saved.username.clone();
+		conn.logon_handler.failed_attempts = saved.failed_attempts;
+		
+		let character = if saved.state == "play" { character::load_character(saved.username.clone()) } else { None };
+		match character
+		{
+			Some(character) =>
 			{
-			let mut last_update = self.lastUpdate.clone();
-			let dbclient = self.dbclient.clone();
-			let messages = dbclient.getRecord(last_update.clone());
-			
-			for message in messages
+				conn.state = ConnectionState::Play;
+				conn.logon_handler.logon_state = LogonState::Done;
+				match game.enter_world(character)
+				{
+					Ok(description) => conn.send(connection::COPYOVER_DONE.to_string() + &description),
+					Err(message) =>
+					{
+						conn.send(message.to_string());
+						conn.close();
+					},
+				}
+			},
+			None if saved.state == "logon" =>
 			{
-				info!("Has message");
-				if message.time_created > last_update
+				if saved.logon_state == "password"
+				{
+					conn.logon_handler.logon_state = LogonState::Password;
+					conn.send(connection::COPYOVER_DONE.to_string() + greeting::ENTER_PASSWORD);
+				}
+				else
 				{
-					last_update = message.time_created.clone();
-					self.send(message.message.clone());
+					conn.logon_handler.username = String::new();
+					conn.send(connection::COPYOVER_DONE.to_string() + greeting::WELCOME_MESSAGE);
 				}
+			},
+			// character creation starts over, like after logging on again
+			None =>
+			{
+				conn.state = ConnectionState::CharacterCreation;
+				conn.logon_handler.logon_state = LogonState::Done;
+				conn.character_creator = CharCreator::new_from_data(saved.username, character::Character::new(), CreationState::Race, String::new());
+				conn.send(connection::COPYOVER_DONE.to_string() + character_messages::RESUME_CREATION + character_messages::RACESELECTION);
+			},
+		}
+		conn
+	}
+	
+	/// Telnet options negotiated with the client so far
+	pub fn telnet_options(&self) -> &telnet::TelnetOptions
+	{
+		self.telnet.options()
+	}
+	
+	/// Put a logged on user back where they left off.
+	///
+	/// Finished characters are loaded from storage and go straight to play, anyone whose
+	/// stored stage is still creation is sent back to the character creator.
+	fn enter_game(&mut self, game: &mut GameHandler, bus: &mut MessageBus)
+	{
+		let username = self.logon_handler.username.clone();
+		
+		// a character left behind by a dropped connection is still in the world, and newer than its save
+		if let Some(description) = game.reconnect(&username)
+		{
+			self.state = ConnectionState::Play;
+			self.send(connection::RECONNECTED.to_string() + &description);
+			
+			let room = game.world().player(&username).map(|c| c.room.clone()).unwrap_or(String::new());
+			let text = format!("{} has reconnected.\r\n", username);
+			bus.publish(Envelope::new(self.token, Target::Room(room), text));
+			return;
+		}
+		
+		// still played on another connection, the in-world character is the only copy that counts
+		if let Some(room) = game.world().player(&username).map(|c| c.room.clone())
+		{
+			self.state = ConnectionState::Play;
+			self.took_over = true;
+			let description = game.world().describe_room(&room, &username);
+			self.send(connection::RECONNECTED.to_string() + &description);
+			return;
+		}
+		
+		let loaded = if logon::get_stage(username.clone()) == logon::STAGE_PLAY
+		{
+			character::load_character(username.clone())
+		}
+		else
+		{
+			None
+		};
+		
+		match loaded
+		{
+			Some(character) =>
+			{
+				self.character_creator = CharCreator::new_from_data(username, character, CreationState::Done, String::new());
+				self.start_playing(game, bus);
+			},
+			None =>
+			{
+				self.character_creator = CharCreator::new_from_data(username, character::Character::new(), CreationState::Race, String::new());
+				self.state = ConnectionState::CharacterCreation;
+				self.send(character_messages::RESUME_CREATION.to_string() + character_messages::RACESELECTION);
 			}
-			self.set_last_update(last_update.clone());
-			self.writable();
+		}
+	}
+	
+	/// Move the character into the world, showing the room it is in and announcing it there
+	fn start_playing(&mut self, game: &mut GameHandler, bus: &mut MessageBus)
+	{
+		self.state = ConnectionState::Play;
+		
+		let character = mem::replace(&mut self.character_creator.character, character::Character::new());
+		match game.enter_world(character)
+		{
+			Ok(description) => self.send(description),
+			Err(message) =>
+			{
+				self.send(message.to_string());
+				self.close();
+				return;
+			},
+		}
+		
+		let room = game.world().player(self.username()).map(|c| c.room.clone()).unwrap_or(String::new());
+		let text = format!("{} appears.\r\n", self.logon_handler.username);
+		bus.publish(Envelope::new(self.token, Target::Room(room), text));
+	}
+	
+	/// Hide the client's local echo while a password is being typed
+	fn update_echo(&mut self)
+	{
+		let hide_input = match self.logon_handler.logon_state
+		{
+			LogonState::Password |
+			LogonState::RegisterPassword |
+			LogonState::RegisterPasswordConfirm => true,
+			_ => false,
+		};
+		
+		let mut negotiation = self.telnet.request_local(telnet::OPT_ECHO, hide_input);
+		if negotiation.len() > 0
+		{
+			// the client did not echo the newline of the hidden input
+			if !hide_input
+			{
+				negotiation.extend_from_slice(b"\r\n");
 			}
-			_=>{}
+			self.send_message(ByteBuf::from_slice(&negotiation)).
+			unwrap_or_else(|e|
+			{
+				error!("Failed to queue telnet negotiation for {:?}: {:?}", self.token, e);
+			});
+		}
+	}
+	
+	/// True from logging on until the connection closes, character creation included
+	pub fn is_logged_on(&self) -> bool
+	{
+		match self.state
+		{
+			ConnectionState::CharacterCreation |
+			ConnectionState::Play => !self.closing,
+			_ => false,
+		}
+	}
+	
+	/// True once the character was moved into the world, it has to be taken out when the connection goes
+	pub fn in_world(&self) -> bool
+	{
+		match self.state
+		{
+			ConnectionState::Play => true,
+			_ => false,
 		}
 	}
 	
-	pub fn handle_input(&mut self, message: &ByteBuf) -> io::Result<bool>
+	/// Only playing connections receive messages from the bus
+	pub fn is_playing(&self) -> bool
 	{
+		match self.state
+		{
+			ConnectionState::Play => !self.closing,
+			_ => false,
+		}
+	}
+	
+	/// Act on one line of input.
+	///
+	/// sessions are the accounts logged on at the other connections, logging on to one of them
+	/// has to take that session over.
+	pub fn handle_input(&mut self, input_string: String, bus: &mut MessageBus, game: &mut GameHandler, sessions: &[String], rng: &mut CombatRng) -> io::Result<bool>
+	{
+		if self.closing
+		{
+			return Ok(false);
+		}
+		self.last_input = Instant::now();
+		
 		match self.state
 		{
 			ConnectionState::New =>
 			{
 				//greeting
 				self.state = ConnectionState::Logon;
-				let buf:&[u8] = greeting::WELCOME_MESSAGE.as_bytes();
-				self.send_message(ByteBuf::from_slice(&buf)).
-				unwrap_or_else(|e|
-				{
-                        error!("Failed to queue message for {:?}: {:?}", self.token, e);
-				});
+				self.send_text(greeting::WELCOME_MESSAGE);
 			}
 			ConnectionState::Logon =>
 			{
-				let input_string = get_input_string(message.clone());
-				
-				let data_struct = logon::LogonManager::new_from_data(self.logon_handler.username.clone(), self.logon_handler.password.clone(), self.logon_handler.logon_state.clone(), String::new());
-				let result = logon::process_commands(input_string, data_struct);								
+				let data_struct = logon::LogonManager::new_from_data(self.logon_handler.username.clone(), self.logon_handler.password.clone(), self.logon_handler.logon_state.clone(), String::new(), self.logon_handler.failed_attempts);
+				let in_session = sessions.contains(&self.logon_handler.username);
+				let answering_takeover = match self.logon_handler.logon_state
+				{
+					LogonState::Takeover => true,
+					_ => false,
+				};
+				let result = logon::process_commands(input_string, data_struct, in_session);								
 				self.logon_handler = result;
+				
+				// the server moves the other session over once this input is handled
+				if let LogonState::Done = self.logon_handler.logon_state
+				{
+					self.took_over = answering_takeover;
+				}
 
 				let to_send = self.logon_handler.return_msg.clone();
 				
+				self.update_echo();
 				self.send(to_send);
 				
 				match self.logon_handler.logon_state
 				{
 					LogonState::RegisterCreation => {self.state = ConnectionState::CharacterCreation;}
-					LogonState::Done => {self.state = ConnectionState::Play;}
+					LogonState::Done => {self.enter_game(game, bus);}
+					LogonState::Disconnect => {self.close();}
 					_ => {}
 				}
 			},
 			ConnectionState::CharacterCreation =>
 			{
-				let input_string = get_input_string(message.clone());
-				
 				// Create character
 				let character = 
 					character::Character::new_from_data(
@@ -199,36 +523,19 @@ impl Connection {
 				
 				match self.character_creator.state
 				{
-					CreationState::Done => {self.state = ConnectionState::Play;}
+					CreationState::Done => {self.start_playing(game, bus);}
 					_ => {}
 				}
 			},
 			ConnectionState::Play =>
 			{
-				let input_string = get_input_string(message.clone());
-				
-				let character = 
-					character::Character::new_from_data(
-						self.logon_handler.username.clone(),
-						self.character_creator.character.info.clone(),
-						self.character_creator.character.attr.clone());
-					
-				let data_struct = 
-					character::CharCreator::new_from_data(
-						self.logon_handler.username.clone(),
-						character,
-						self.character_creator.state.clone(),
-						String::new());
-				
-				let result = GameHandler::process_commands(input_string, data_struct);
-				
-				let now = time::get_time();
-				let transaction = transactionstorage::Transaction::new(result.clone(), now.clone());
-				let dbclient = self.dbclient.clone();
+				let result = game.process_commands(input_string, self.logon_handler.username.clone(), &mut self.channels, &mut self.colour, rng);
 				
-				dbclient.insertRecord(transaction);
-				
-				println!("Inserting result in play state {:?}", result);
+				self.send(result.reply);
+				for (target, message) in result.messages
+				{
+					bus.publish(Envelope::new(self.token, target, message));
+				}
 			}
 		}
 		Ok(true)
@@ -236,25 +543,25 @@ impl Connection {
 
     /// Handle read event from event loop.
     ///
-    /// Currently only reads a max of 2048 bytes. Excess bytes are dropped on the floor.
-    ///
-    /// The recieve buffer is sent back to `Server` so the message can be broadcast to all
-    /// listening connections.
-    pub fn readable(&mut self) -> io::Result<ByteBuf> {
-
-        // ByteBuf is a heap allocated slice that mio supports internally. We use this as it does
-        // the work of tracking how much of our slice has been used. I chose a capacity of 2048
-        // after reading 
-        // https://github.com/carllerche/mio/blob/eed4855c627892b88f7ca68d3283cbc708a1c2b3/src/io.rs#L23-27
-        // as that seems like a good size of streaming. If you are wondering what the difference
-        // between messaged based and continuous streaming read
-        // http://stackoverflow.com/questions/3017633/difference-between-message-oriented-protocols-and-stream-oriented-protocols
-        // . TLDR: UDP vs TCP. We are using TCP.
-        let mut recv_buf = ByteBuf::mut_with_capacity(2048);
+    /// Reads until the socket is drained. Telnet commands are stripped and answered here, the
+    /// remaining data goes through the input buffer and every complete line is sent back to
+    /// `Server` so it can be handed to `handle_input` as its own command.
+    pub fn readable(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
 
         // we are PollOpt::edge() and PollOpt::oneshot(), so we _must_ drain
         // the entire socket receive buffer, otherwise the server will hang.
         loop {
+            // ByteBuf is a heap allocated slice that mio supports internally. We use this as it does
+            // the work of tracking how much of our slice has been used. I chose a capacity of 2048
+            // after reading 
+            // https://github.com/carllerche/mio/blob/eed4855c627892b88f7ca68d3283cbc708a1c2b3/src/io.rs#L23-27
+            // as that seems like a good size of streaming. If you are wondering what the difference
+            // between messaged based and continuous streaming read
+            // http://stackoverflow.com/questions/3017633/difference-between-message-oriented-protocols-and-stream-oriented-protocols
+            // . TLDR: UDP vs TCP. We are using TCP.
+            let mut recv_buf = ByteBuf::mut_with_capacity(2048);
+
             match self.sock.try_read_buf(&mut recv_buf) {
                 // the socket receive buffer is empty, so let's move on
                 // try_read_buf internally handles WouldBlock here too
@@ -265,18 +572,43 @@ impl Connection {
                 Ok(Some(n)) => {
                     info!("CONN : we read {} bytes", n);
 
+                    // change our type from MutByteBuf to ByteBuf
+                    let capacity = recv_buf.capacity();
+                    let raw = recv_buf.flip();
+                    let decoded = self.telnet.decode(raw.bytes());
+
+                    if decoded.reply.len() > 0 {
+                        try!(self.send_message(ByteBuf::from_slice(&decoded.reply)));
+                    }
+
+                    for (option, payload) in decoded.subnegotiations {
+                        if option == telnet::OPT_TTYPE {
+                            if let Some(terminal) = telnet::terminal_type(&payload) {
+                                info!("{:?} uses terminal {}", self.token, terminal);
+                                self.colour.terminal(&terminal);
+                            }
+                        }
+                    }
+
+                    for line in self.input_buffer.push(&decoded.data) {
+                        match line {
+                            Line::Complete(input) => lines.push(input),
+                            Line::TooLong => {
+                                let error = format!("{} ({} characters)\r\n",
+                                    connection::LINE_TOO_LONG, linebuffer::MAX_LINE_LENGTH);
+                                self.send(error);
+                            }
+                        }
+                    }
+
                     // if we read less than capacity, then we know the
                     // socket is empty and we should stop reading. if we
                     // read to full capacity, we need to keep reading so we
-                    // can drain the socket. if the client sent exactly capacity,
-                    // we will match the arm above. the recieve buffer will be
-                    // full, so extra bytes are being dropped on the floor. to
-                    // properly handle this, i would need to push the data into
-                    // a growable Vec<u8>.
-                    if n < recv_buf.capacity() {
+                    // can drain the socket. partial lines wait in the input
+                    // buffer until the rest of them arrives.
+                    if n < capacity {
                         break;
                     }
-					
                 },
                 Err(e) => {
                     println!("Failed to read buffer for token {:?}, error: {}", self.token, e);
@@ -285,70 +617,34 @@ impl Connection {
             }
         }
 
-        // change our type from MutByteBuf to ByteBuf
-        Ok(recv_buf.flip())
+        Ok(lines)
     }
 
     /// Handle a writable event from the event loop.
     ///
-    /// Send one message from the send queue to the client. If the queue is empty, remove interest
-    /// in write events.
-    /// TODO: Figure out if sending more than one message is optimal. Maybe we should be trying to
-    /// flush until the kernel sends back EAGAIN?
-    pub fn writable(&mut self) -> io::Result<()> {				
-		if !self.send_queue.is_empty() && self.send_queue.len() > 0
-		{
-			info!("Sending message to client");
-			try!(self.send_queue.pop()
-				.ok_or(Error::new(ErrorKind::Other, "Could not pop send queue"))
-				.and_then(|mut buf| {
-					match self.sock.try_write_buf(&mut buf) {
-						Ok(None) => {
-							println!("client flushing buf; WouldBlock");
-
-							// put message back into the queue so we can try again
-							self.send_queue.push(buf);
-							Ok(())
-						},
-						Ok(Some(n)) => {
-							info!("CONN : we wrote {} bytes", n);
-							Ok(())
-						},
-						Err(e) => {
-							println!("Failed to send buffer for {:?}, error: {}", self.token, e);
-							Err(e)
-						}
-					}
-				})
-			);
+    /// Write queued output until it is all sent or the socket would block, whatever is left goes
+    /// out on the next writable event. Once nothing is left, remove interest in write events.
+    pub fn writable(&mut self) -> io::Result<()> {
+		match self.output.write_to(&mut self.sock) {
+			Ok(n) => info!("CONN : we wrote {} bytes", n),
+			Err(e) => {
+				println!("Failed to send buffer for {:?}, error: {}", self.token, e);
+				return Err(e);
+			}
 		}
-		
-        if self.send_queue.is_empty() || self.send_queue.len() == 0 {
+
+        if self.output.is_empty() {
             self.interest.remove(EventSet::writable());
         }
 
         Ok(())
     }
 	
-	/// Welcome socket to the world
-	pub fn welcome(&mut self)  -> io::Result<()> {
-		match self.sock.try_write(greeting::WELCOME_MESSAGE.as_bytes()) {
-			Ok(None) => {
-				info!("client flushing buf; WouldBlock");
-
-				// put message back into the queue so we can try again
-				self.welcome();
-				Ok(())
-			},
-			Ok(Some(n)) => {
-				info!("CONN : we wrote {} bytes", n);
-				Ok(())
-			},
-			Err(e) => {
-				println!("Failed to send buffer for {:?}, error: {}", self.token, e);
-				Err(e)
-			}
-		}				
+	/// Welcome socket to the world, and ask its terminal type to find out if it shows colour
+	pub fn welcome(&mut self) {
+		let negotiation = self.telnet.request_remote(telnet::OPT_TTYPE, true);
+		self.send_message(ByteBuf::from_slice(&negotiation)).ok();
+		self.send_text(greeting::WELCOME_MESSAGE);
 	}
 
 	pub fn send(&mut self, message: String){
@@ -356,19 +652,37 @@ impl Connection {
 		{
 			let now = time::now();	
 			let s = format!("[{}:{}:{}]{}", now.tm_hour, now.tm_min, now.tm_sec, message);	
-			let bytes = ByteBuf::from_slice(s.as_bytes());	
-			self.send_message(bytes);		
+			self.send_text(&s);
 		}
 	}
 	
+	/// Queue text with its colour codes rendered or stripped for this client
+	fn send_text(&mut self, text: &str)
+	{
+		let text = self.colour.apply(text);
+		let bytes = ByteBuf::from_slice(&telnet::escape(text.as_bytes()));
+		self.send_message(bytes).ok();
+	}
+	
     /// Queue an outgoing message to the client.
     ///
     /// This will cause the connection to register interests in write events with the event loop.
     /// The connection can still safely have an interest in read events. The read and write buffers
     /// operate independently of each other.
+    ///
+    /// A client that lets its output pile up past the high-water mark has stopped reading, it is
+    /// closed without waiting for the rest to be sent.
     pub fn send_message(&mut self, message: ByteBuf) -> io::Result<()> {
 		info!("send message queued");
-        self.send_queue.push(message);
+		if self.stalled {
+			return Err(Error::new(ErrorKind::Other, "client stopped reading"));
+		}
+		if !self.output.push(message.bytes()) {
+			println!("Disconnecting {:?} from {}, {} bytes of output are waiting", self.token, self.addr, self.output.len());
+			self.stalled = true;
+			self.close();
+			return Err(Error::new(ErrorKind::Other, "client stopped reading"));
+		}
         self.interest.insert(EventSet::writable());
         Ok(())
     }
@@ -402,4 +716,58 @@ impl Connection {
             Err(e)
         })
     }
+}
+
+#[cfg(all(test, unix))]
+mod tests
+{
+	use super::*;
+	use std::net;
+	use std::os::unix::io::{FromRawFd, IntoRawFd};
+	use std::rc::Rc;
+	use combat;
+	use password;
+	use storage;
+	use world::World;
+
+	// the server side of a loopback connection
+	fn connection() -> (Connection, net::TcpStream)
+	{
+		let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (server, addr) = listener.accept().unwrap();
+		let sock = unsafe { TcpStream::from_raw_fd(server.into_raw_fd()) };
+		(Connection::new(sock, Token(1), addr), client)
+	}
+
+	#[test]
+	fn closes_after_too_many_wrong_passwords()
+	{
+		storage::set_db(Rc::new(storage::MemoryStorage::new()));
+		let record = logon::PlayerRecord
+		{
+			username: "alice".to_string(),
+			password: password::hash_password("secret").unwrap(),
+			stage: logon::STAGE_PLAY.to_string(),
+		};
+		storage::save(&*storage::get_db(), &record).unwrap();
+
+		let (mut conn, _client) = connection();
+		let mut bus = MessageBus::new();
+		let mut game = GameHandler::new(World::default_world());
+		let mut rng = combat::seeded_rng(1);
+		let mut enter = |conn: &mut Connection, line: &str| conn.handle_input(line.to_string(), &mut bus, &mut game, &[], &mut rng).unwrap();
+
+		enter(&mut conn, "alice");
+		for attempt in 1..logon::MAX_PASSWORD_ATTEMPTS
+		{
+			enter(&mut conn, "wrong");
+			assert_eq!(conn.logon_handler.failed_attempts, attempt);
+			assert!(!conn.is_closing());
+		}
+		enter(&mut conn, "wrong");
+		assert!(conn.is_closing());
+		// nothing more is read from a closing connection
+		assert!(!enter(&mut conn, "secret"));
+	}
 }
\ No newline at end of file
@@ -0,0 +1,296 @@
+// Copyover
+// A hot reboot for deploys: every live connection is written to a state file, the new build is
+// exec'd with the listening socket and the client sockets still open, and it picks the sessions
+// up from that file so nobody has to log in again. Characters go through storage like on any
+// other save, the world itself starts fresh from the area files. Link-dead characters come back
+// link-dead, with what was left of their grace period.
+
+extern crate libc;
+extern crate toml;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use self::toml::{Parser, Value};
+
+use colour::ColourPreference;
+use telnet::TelnetOptions;
+
+pub const STATE_PATH: &'static str = "./copyover.toml";
+
+// tells a starting server to restore the sessions in the state file that follows
+pub const ARG: &'static str = "--copyover";
+
+/// What a connection needs to carry on in the new process
+pub struct SavedConnection
+{
+	pub fd: RawFd,
+	pub token: usize,
+	// logon, creation or play
+	pub state: String,
+	pub username: String,
+	// username or password, where a logon is picked up again
+	pub logon_state: String,
+	pub failed_attempts: i32,
+	pub channels: Vec<String>,
+	pub colour: ColourPreference,
+	pub telnet: TelnetOptions,
+}
+
+/// A character a dropped connection left in the world, still waiting for its player
+pub struct SavedLinkDead
+{
+	pub username: String,
+	// seconds of the grace period used up already
+	pub elapsed: u64,
+}
+
+pub struct State
+{
+	pub listener: RawFd,
+	pub connections: Vec<SavedConnection>,
+	pub link_dead: Vec<SavedLinkDead>,
+}
+
+fn bytes(list: &[u8]) -> Value
+{
+	Value::Array(list.iter().map(|byte| Value::Integer(*byte as i64)).collect())
+}
+
+fn strings(list: &[String]) -> Value
+{
+	Value::Array(list.iter().map(|text| Value::String(text.clone())).collect())
+}
+
+// a missing or mistyped field is an error, the sessions can't be trusted then
+fn field<'a>(table: &'a Value, name: &str) -> Result<&'a Value, String>
+{
+	table.lookup(name).ok_or(format!("{} is missing", name))
+}
+
+fn integer(table: &Value, name: &str) -> Result<i64, String>
+{
+	try!(field(table, name)).as_integer().ok_or(format!("{} should be a number", name))
+}
+
+fn text(table: &Value, name: &str) -> Result<String, String>
+{
+	try!(field(table, name)).as_str().map(|text| text.to_string()).ok_or(format!("{} should be a string", name))
+}
+
+fn flag(table: &Value, name: &str) -> Result<bool, String>
+{
+	try!(field(table, name)).as_bool().ok_or(format!("{} should be true or false", name))
+}
+
+fn list(table: &Value, name: &str) -> Result<Vec<Value>, String>
+{
+	try!(field(table, name)).as_slice().map(|values| values.to_vec()).ok_or(format!("{} should be a list", name))
+}
+
+impl SavedConnection
+{
+	fn to_value(&self) -> Value
+	{
+		let mut table = BTreeMap::new();
+		table.insert("fd".to_string(), Value::Integer(self.fd as i64));
+		table.insert("token".to_string(), Value::Integer(self.token as i64));
+		table.insert("state".to_string(), Value::String(self.state.clone()));
+		table.insert("username".to_string(), Value::String(self.username.clone()));
+		table.insert("logon_state".to_string(), Value::String(self.logon_state.clone()));
+		table.insert("failed_attempts".to_string(), Value::Integer(self.failed_attempts as i64));
+		table.insert("channels".to_string(), strings(&self.channels));
+		table.insert("colour".to_string(), Value::Boolean(self.colour.enabled));
+		table.insert("colour_chosen".to_string(), Value::Boolean(self.colour.chosen));
+		table.insert("telnet_local".to_string(), bytes(&self.telnet.local));
+		table.insert("telnet_remote".to_string(), bytes(&self.telnet.remote));
+		Value::Table(table)
+	}
+
+	fn from_value(table: &Value) -> Result<SavedConnection, String>
+	{
+		let option = |value: &Value| value.as_integer().map(|number| number as u8);
+		let channels = try!(list(table, "channels")).iter().filter_map(|value| value.as_str().map(|text| text.to_string())).collect();
+		let local = try!(list(table, "telnet_local")).iter().filter_map(&option).collect();
+		let remote = try!(list(table, "telnet_remote")).iter().filter_map(&option).collect();
+		Ok(SavedConnection
+		{
+			fd: try!(integer(table, "fd")) as RawFd,
+			token: try!(integer(table, "token")) as usize,
+			state: try!(text(table, "state")),
+			username: try!(text(table, "username")),
+			logon_state: try!(text(table, "logon_state")),
+			failed_attempts: try!(integer(table, "failed_attempts")) as i32,
+			channels: channels,
+			colour: ColourPreference { enabled: try!(flag(table, "colour")), chosen: try!(flag(table, "colour_chosen")) },
+			telnet: TelnetOptions { local: local, remote: remote },
+		})
+	}
+}
+
+impl SavedLinkDead
+{
+	fn to_value(&self) -> Value
+	{
+		let mut table = BTreeMap::new();
+		table.insert("username".to_string(), Value::String(self.username.clone()));
+		table.insert("elapsed".to_string(), Value::Integer(self.elapsed as i64));
+		Value::Table(table)
+	}
+
+	fn from_value(table: &Value) -> Result<SavedLinkDead, String>
+	{
+		Ok(SavedLinkDead
+		{
+			username: try!(text(table, "username")),
+			elapsed: try!(integer(table, "elapsed")) as u64,
+		})
+	}
+}
+
+impl State
+{
+	pub fn save(&self, path: &str) -> io::Result<()>
+	{
+		let mut table = BTreeMap::new();
+		table.insert("listener".to_string(), Value::Integer(self.listener as i64));
+		table.insert("connections".to_string(), Value::Array(self.connections.iter().map(|conn| conn.to_value()).collect()));
+		table.insert("link_dead".to_string(), Value::Array(self.link_dead.iter().map(|player| player.to_value()).collect()));
+
+		let mut file = try!(File::create(path));
+		write!(file, "{}", Value::Table(table))
+	}
+
+	pub fn load(path: &str) -> Result<State, String>
+	{
+		let mut source = String::new();
+		if let Err(e) = File::open(path).and_then(|mut file| file.read_to_string(&mut source))
+		{
+			return Err(format!("{}: {}", path, e));
+		}
+		let table = match Parser::new(&source).parse()
+		{
+			Some(table) => Value::Table(table),
+			None => return Err(format!("{} is not a copyover state file", path)),
+		};
+
+		let mut connections = Vec::new();
+		for conn in try!(list(&table, "connections")).iter()
+		{
+			connections.push(try!(SavedConnection::from_value(conn)));
+		}
+		// missing when the old process was built before link-dead players were carried over
+		let mut link_dead = Vec::new();
+		if table.lookup("link_dead").is_some()
+		{
+			for player in try!(list(&table, "link_dead")).iter()
+			{
+				link_dead.push(try!(SavedLinkDead::from_value(player)));
+			}
+		}
+		Ok(State
+		{
+			listener: try!(integer(&table, "listener")) as RawFd,
+			connections: connections,
+			link_dead: link_dead,
+		})
+	}
+}
+
+/// Keep fd open in the process exec starts
+pub fn inherit(fd: RawFd) -> io::Result<()>
+{
+	unsafe
+	{
+		let flags = libc::fcntl(fd, libc::F_GETFD);
+		if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0
+		{
+			return Err(io::Error::last_os_error());
+		}
+	}
+	Ok(())
+}
+
+/// Take the copyover argument and its state file out of the command line
+pub fn take_arg(args: &mut Vec<String>) -> Option<String>
+{
+	let index = match args.iter().position(|arg| arg == ARG)
+	{
+		Some(index) if index + 1 < args.len() => index,
+		_ => return None,
+	};
+	let path = args.remove(index + 1);
+	args.remove(index);
+	Some(path)
+}
+
+/// Replace this process with the server binary on disk, told to restore the state at path.
+///
+/// Only returns when that failed.
+pub fn exec(path: &str) -> io::Error
+{
+	// the path we were started by rather than current_exe, which names the old, deleted file
+	// once a deploy replaced it
+	let mut args: Vec<String> = env::args().collect();
+	let program = args.remove(0);
+	take_arg(&mut args);
+	Command::new(program).args(&args).arg(ARG).arg(path).exec()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use colour::ColourPreference;
+	use telnet::TelnetOptions;
+
+	#[test]
+	fn state_survives_the_file()
+	{
+		let file = ::std::env::temp_dir().join("copyover-test.toml");
+		let path = file.to_str().unwrap();
+		let state = State
+		{
+			listener: 3,
+			connections: vec![SavedConnection
+			{
+				fd: 7,
+				token: 2,
+				state: "play".to_string(),
+				username: "Alice".to_string(),
+				logon_state: "username".to_string(),
+				failed_attempts: 1,
+				channels: vec!["ooc".to_string()],
+				colour: ColourPreference { enabled: true, chosen: false },
+				telnet: TelnetOptions { local: vec![1, 3], remote: vec![24] },
+			}],
+			link_dead: vec![SavedLinkDead { username: "Bob".to_string(), elapsed: 42 }],
+		};
+		state.save(path).unwrap();
+
+		let loaded = State::load(path).unwrap();
+		::std::fs::remove_file(path).ok();
+		assert_eq!(loaded.listener, 3);
+		let conn = &loaded.connections[0];
+		assert_eq!((conn.fd, conn.token, &conn.state[..], &conn.username[..]), (7, 2, "play", "Alice"));
+		assert_eq!(conn.channels, vec!["ooc".to_string()]);
+		assert!(conn.colour.enabled && !conn.colour.chosen);
+		assert_eq!(conn.telnet.local, vec![1, 3]);
+		assert_eq!(conn.telnet.remote, vec![24]);
+		assert_eq!((&loaded.link_dead[0].username[..], loaded.link_dead[0].elapsed), ("Bob", 42));
+	}
+
+	#[test]
+	fn takes_the_copyover_argument()
+	{
+		let mut args: Vec<String> = vec!["--tick-ms", "500", "--copyover", "./state.toml"].iter().map(|arg| arg.to_string()).collect();
+		assert_eq!(take_arg(&mut args), Some("./state.toml".to_string()));
+		assert_eq!(args, vec!["--tick-ms".to_string(), "500".to_string()]);
+	}
+}
@@ -0,0 +1,205 @@
+// Wear locations, the commands that use them and the attributes equipment adds up to
+
+use std::collections::HashMap;
+
+use character::{Character, ATTRIBUTES};
+use commands::{Args, ArgSpec, Command, CommandOutput, CommandRegistry};
+use gamehandler::CommandContext;
+use item::Item;
+use world::World;
+use Messages::game;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum WearSlot
+{
+	Head,
+	Body,
+	Hands,
+	Weapon,
+	Shield,
+	LeftRing,
+	RightRing,
+}
+
+// the order equipment is listed in
+pub const WEAR_SLOTS: [WearSlot; 7] = [WearSlot::Head, WearSlot::Body, WearSlot::Hands, WearSlot::Weapon,
+	WearSlot::Shield, WearSlot::LeftRing, WearSlot::RightRing];
+
+impl WearSlot
+{
+	pub fn name(&self) -> &'static str
+	{
+		match *self
+		{
+			WearSlot::Head => "head",
+			WearSlot::Body => "body",
+			WearSlot::Hands => "hands",
+			WearSlot::Weapon => "weapon",
+			WearSlot::Shield => "shield",
+			WearSlot::LeftRing => "left ring",
+			WearSlot::RightRing => "right ring",
+		}
+	}
+
+	/// The wear location named in an area file, a ring may go on either hand
+	pub fn parse(name: &str) -> Option<WearSlot>
+	{
+		match name
+		{
+			"ring" => Some(WearSlot::LeftRing),
+			_ => WEAR_SLOTS.iter().find(|slot| slot.name() == name).cloned(),
+		}
+	}
+
+	/// Every slot an item made for this one can be worn in
+	pub fn fits(&self) -> Vec<WearSlot>
+	{
+		match *self
+		{
+			WearSlot::LeftRing | WearSlot::RightRing => vec![WearSlot::LeftRing, WearSlot::RightRing],
+			slot => vec![slot],
+		}
+	}
+}
+
+/// Base attributes plus the modifiers of everything the character wears
+pub fn effective_attributes(character: &Character, world: &World) -> HashMap<String, i32>
+{
+	let mut attributes = HashMap::new();
+	for name in ATTRIBUTES.iter()
+	{
+		attributes.insert(name.to_string(), character.base_attr(name));
+	}
+
+	for &(_, ref item) in character.equips.iter()
+	{
+		if let Some(prototype) = world.get_item(&item.prototype)
+		{
+			for &(ref attribute, modifier) in prototype.modifiers.iter()
+			{
+				*attributes.entry(attribute.clone()).or_insert(0) += modifier;
+			}
+		}
+	}
+	attributes
+}
+
+pub fn register_commands(commands: &mut CommandRegistry)
+{
+	commands.register(Command::new("wear", wear).alias("wield")
+		.arg(ArgSpec::word("item"))
+		.help("Wear or wield an item you carry"));
+	commands.register(Command::new("remove", remove)
+		.arg(ArgSpec::word("item"))
+		.help("Take off something you wear"));
+	commands.register(Command::new("equipment", equipment).alias("eq")
+		.help("List what you are wearing"));
+}
+
+fn wear(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let word = args.word(0).unwrap_or("");
+	let index = match context.world.find_item(&context.character().items, word)
+	{
+		Some(index) => index,
+		None => return CommandOutput::reply(game::NOT_CARRYING.to_string()),
+	};
+
+	let item = context.character().items[index].clone();
+	let wear_slot = match context.world.get_item(&item.prototype).and_then(|prototype| prototype.wear)
+	{
+		Some(slot) => slot,
+		None => return CommandOutput::reply(game::CANT_WEAR.to_string()),
+	};
+
+	let free = wear_slot.fits().into_iter()
+		.find(|slot| !context.character().equips.iter().any(|&(worn, _)| worn == *slot));
+	let slot = match free
+	{
+		Some(slot) => slot,
+		None => return CommandOutput::reply(format!("You are already wearing something on your {}.\r\n", wear_slot.name())),
+	};
+
+	let character = context.character_mut();
+	character.items.remove(index);
+	character.equips.push((slot, item.clone()));
+
+	let name = context.world.item_name(&item);
+	let room = context.character().room.clone();
+	let mut output = CommandOutput::reply(format!("You wear {} on your {}.\r\n", name, slot.name()));
+	output.to_room(&room, format!("{} wears {}.\r\n", context.username, name));
+	output
+}
+
+fn remove(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let word = args.word(0).unwrap_or("");
+	let worn: Vec<Item> = context.character().equips.iter().map(|&(_, ref item)| item.clone()).collect();
+	let index = match context.world.find_item(&worn, word)
+	{
+		Some(index) => index,
+		None => return CommandOutput::reply(game::NOT_WEARING.to_string()),
+	};
+
+	let character = context.character_mut();
+	let (_, item) = character.equips.remove(index);
+	character.items.push(item.clone());
+
+	let name = context.world.item_name(&item);
+	let room = context.character().room.clone();
+	let mut output = CommandOutput::reply(format!("You stop using {}.\r\n", name));
+	output.to_room(&room, format!("{} stops using {}.\r\n", context.username, name));
+	output
+}
+
+fn equipment(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	let character = context.character();
+	let mut text = String::from("You are using:\r\n");
+	for slot in WEAR_SLOTS.iter()
+	{
+		let worn = character.equips.iter()
+			.find(|&&(worn, _)| worn == *slot)
+			.map(|&(_, ref item)| context.world.item_name(item))
+			.unwrap_or("nothing".to_string());
+		text = text + &format!("  {:<12} {}\r\n", format!("<{}>", slot.name()), worn);
+	}
+	CommandOutput::reply(text)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use item::{ItemPrototype, ItemType};
+
+	#[test]
+	fn equipment_adds_to_base_attributes()
+	{
+		let mut world = World::default_world();
+		world.add_item(ItemPrototype
+		{
+			id: "ring".to_string(),
+			name: "a ring".to_string(),
+			keywords: vec!["ring".to_string()],
+			description: String::new(),
+			weight: 1,
+			value: 1,
+			item_type: ItemType::Treasure,
+			flags: Vec::new(),
+			wear: Some(WearSlot::LeftRing),
+			modifiers: vec![("str".to_string(), 2)],
+		});
+
+		let mut character = Character::new();
+		character.attr.insert("str".to_string(), 4);
+		character.equips.push((WearSlot::LeftRing, Item::new("ring")));
+		character.equips.push((WearSlot::RightRing, Item::new("ring")));
+
+		let attributes = effective_attributes(&character, &world);
+		assert_eq!(attributes.get("str"), Some(&8));
+		assert_eq!(character.base_attr("str"), 4);
+		assert_eq!(character.carry_capacity(&world), 130);
+		assert_eq!(WearSlot::parse("ring").unwrap().fits(), vec![WearSlot::LeftRing, WearSlot::RightRing]);
+	}
+}
@@ -0,0 +1,149 @@
+// Experience and levels
+// Experience comes from kills and from anything else that calls award, like quests. Every level
+// raises hp, mana and stam and hands out training points for the other attributes.
+
+use character;
+use character::Character;
+use commands::{Args, ArgSpec, Command, CommandOutput, CommandRegistry};
+use gamehandler::CommandContext;
+use world::World;
+use Messages::game;
+
+pub const MAX_LEVEL: i32 = 50;
+
+// attributes training points can be spent on
+pub const TRAINABLE: [&'static str; 4] = ["str", "agi", "int", "charm"];
+
+// training stops here, equipment can still add more
+pub const TRAIN_MAX: i32 = 25;
+
+// what every level adds to the base attributes
+const LEVEL_GAINS: [(&'static str, i32); 3] = [("hp", 5), ("mana", 3), ("stam", 3)];
+
+const TRAINS_PER_LEVEL: i32 = 1;
+const PRACTICES_PER_LEVEL: i32 = 2;
+
+/// Total experience needed to get past level, 100 for level 1, 300 for level 2 and so on
+pub fn xp_for_next(level: i32) -> i32
+{
+	50 * level * (level + 1)
+}
+
+/// Experience for killing something with max_hp health
+pub fn kill_xp(max_hp: i32) -> i32
+{
+	10 + 2 * max_hp.max(0)
+}
+
+/// Add experience to a character and raise its level as often as it has earned.
+///
+/// Returns what to tell the player.
+pub fn gain_xp(character: &mut Character, amount: i32) -> String
+{
+	if amount <= 0 || character.level >= MAX_LEVEL
+	{
+		return String::new();
+	}
+
+	character.xp += amount;
+	let mut text = format!("You gain {} experience.\r\n", amount);
+	while character.level < MAX_LEVEL && character.xp >= xp_for_next(character.level)
+	{
+		character.level += 1;
+		for &(name, gain) in LEVEL_GAINS.iter()
+		{
+			let value = character.base_attr(name) + gain;
+			character.attr.insert(name.to_string(), value);
+		}
+		// a new level comes with the health to go with it
+		character.hp += LEVEL_GAINS[0].1;
+		character.trains += TRAINS_PER_LEVEL;
+		character.practices += PRACTICES_PER_LEVEL;
+		text = text + &format!("You raise a level! You are now level {}.\r\n", character.level);
+	}
+	text
+}
+
+/// Give a player experience and tell them about it
+pub fn award(world: &mut World, username: &str, amount: i32, output: &mut CommandOutput)
+{
+	let text = match world.player_mut(username)
+	{
+		Some(character) => gain_xp(character, amount),
+		None => return,
+	};
+	if !text.is_empty()
+	{
+		// progress is saved right away so a crash can't take a level back
+		character::save_character(world.player(username).unwrap());
+		output.to_user(username, text);
+	}
+}
+
+pub fn register_commands(commands: &mut CommandRegistry)
+{
+	commands.register(Command::new("train", train)
+		.arg(ArgSpec::word("attribute").optional())
+		.help("Spend a training point on str, agi, int or charm"));
+}
+
+fn train(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let name = match args.word(0)
+	{
+		Some(word) => match TRAINABLE.iter().find(|name| **name == word.to_lowercase())
+		{
+			Some(name) => *name,
+			None => return CommandOutput::reply(game::CANT_TRAIN.to_string()),
+		},
+		None =>
+		{
+			let character = context.character();
+			let mut text = format!("You have {} training points.\r\n", character.trains);
+			for name in TRAINABLE.iter()
+			{
+				text = text + &format!("  {:<6} {:>3}\r\n", name, character.base_attr(name));
+			}
+			return CommandOutput::reply(text);
+		},
+	};
+
+	let character = context.character_mut();
+	if character.trains <= 0
+	{
+		return CommandOutput::reply(game::NO_TRAINS.to_string());
+	}
+	let value = character.base_attr(name);
+	if value >= TRAIN_MAX
+	{
+		return CommandOutput::reply(format!("Your {} can't be trained any further.\r\n", name));
+	}
+
+	character.attr.insert(name.to_string(), value + 1);
+	character.trains -= 1;
+	character::save_character(character);
+	CommandOutput::reply(format!("Your {} rises to {}.\r\n", name, value + 1))
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn levels_follow_the_curve()
+	{
+		let mut character = Character::new();
+		assert_eq!(character.level, 1);
+		let hp = character.base_attr("hp");
+
+		gain_xp(&mut character, 99);
+		assert_eq!(character.level, 1);
+		let text = gain_xp(&mut character, 250);
+		assert_eq!(character.level, 3);
+		assert_eq!(text.matches("You raise a level!").count(), 2);
+		assert_eq!(character.base_attr("hp"), hp + 10);
+		assert_eq!(character.trains, 2);
+		assert_eq!(xp_for_next(character.level), 600);
+	}
+}
@@ -1,22 +1,536 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use character;
-use character::CharCreator;
+use colour;
+use colour::ColourPreference;
+use character::{Character, ATTRIBUTES};
+use commands::{Args, ArgSpec, Command, CommandOutput, CommandRegistry};
+use inventory;
+use equipment;
+use combat;
+use combat::CombatRng;
+use mob;
+use skills;
+use experience;
+use limits;
+use shutdown;
+use shutdown::Request;
+use world::{Direction, World, DIRECTIONS};
+use Messages::{connection, game};
 
-use std::vec;
-use std::collections::HashMap;
+//channels anyone can subscribe to, and the ones new connections start on
+pub const CHANNELS: [&'static str; 3] = ["ooc", "newbie", "builder"];
+pub const DEFAULT_CHANNELS: [&'static str; 2] = ["ooc", "newbie"];
+
+//ticks between area resets, that bring back killed mobs and taken items
+pub const RESET_TICKS: u64 = 60;
+//ticks between saves of everyone playing, so walking around never waits on the disk
+pub const SAVE_TICKS: u64 = 30;
+
+//everything a command handler may look at or change
+pub struct CommandContext<'a>
+{
+	pub username: String,
+	pub channels: &'a mut Vec<String>,
+	pub colour: &'a mut ColourPreference,
+	pub commands: &'a CommandRegistry,
+	pub world: &'a mut World,
+	//whether the player may use admin commands
+	pub admin: bool,
+	pub stats: &'a ServerStats,
+	//a shutdown or copyover an admin asked for
+	pub shutdown: &'a mut Option<Request>,
+	//the server's random numbers, shared with the combat rounds
+	pub rng: &'a mut CombatRng,
+}
+
+//connection counts kept up to date by the server, for admins to look at
+pub struct ServerStats
+{
+	pub connections: usize,
+	pub max_connections: usize,
+	pub max_per_ip: usize,
+	//connections turned away because the server was full or the address had too many
+	pub rejected_full: u64,
+	pub rejected_per_ip: u64,
+}
+
+impl ServerStats
+{
+	pub fn new() -> ServerStats
+	{
+		ServerStats
+		{
+			connections: 0,
+			max_connections: 0,
+			max_per_ip: 0,
+			rejected_full: 0,
+			rejected_per_ip: 0,
+		}
+	}
+}
+
+impl<'a> CommandContext<'a>
+{
+	//a playing connection always has its character in the world
+	pub fn character(&self) -> &Character
+	{
+		self.world.player(&self.username).expect("player is not in the world")
+	}
+	
+	pub fn character_mut(&mut self) -> &mut Character
+	{
+		self.world.player_mut(&self.username).expect("player is not in the world")
+	}
+}
 
 pub struct GameHandler
 {
+	commands: CommandRegistry,
+	world: World,
+	ticks: u64,
+	//usernames of the admins, matched exactly
+	admins: Vec<String>,
+	stats: ServerStats,
+	//usernames of players whose connection dropped, and when it happened
+	link_dead: HashMap<String, Instant>,
+	//how long those characters wait in the world for their player
+	link_dead_grace: Duration,
+	//left here by the shutdown command for the server to pick up
+	shutdown_request: Option<Request>,
 }
 
 impl GameHandler
 {	
-	pub fn process_commands(cmd:String, data: CharCreator) -> String
+	pub fn new(world: World) -> GameHandler
+	{
+		let mut commands = CommandRegistry::new();
+		register_commands(&mut commands);
+		inventory::register_commands(&mut commands);
+		equipment::register_commands(&mut commands);
+		combat::register_commands(&mut commands);
+		skills::register_commands(&mut commands);
+		experience::register_commands(&mut commands);
+		colour::register_commands(&mut commands);
+		
+		let mut world = world;
+		world.reset();
+		
+		GameHandler
+		{
+			commands: commands,
+			world: world,
+			ticks: 0,
+			admins: Vec::new(),
+			stats: ServerStats::new(),
+			link_dead: HashMap::new(),
+			link_dead_grace: Duration::from_secs(300),
+			shutdown_request: None,
+		}
+	}
+	
+	pub fn world(&self) -> &World
+	{
+		&self.world
+	}
+	
+	pub fn set_admins(&mut self, admins: Vec<String>)
 	{
-		let mut char_info = data.character.info.clone();
-		let mut char_attributes = data.character.attr.clone();
-		let mut user_name = data.username.clone();
+		self.admins = admins;
+	}
+	
+	pub fn is_admin(&self, username: &str) -> bool
+	{
+		self.admins.iter().any(|admin| admin == username)
+	}
+	
+	pub fn stats_mut(&mut self) -> &mut ServerStats
+	{
+		&mut self.stats
+	}
+	
+	/// The shutdown or copyover an admin asked for since the last call
+	pub fn take_shutdown_request(&mut self) -> Option<Request>
+	{
+		self.shutdown_request.take()
+	}
+	
+	/// Save every character in the world, link-dead ones too
+	pub fn save_all(&self)
+	{
+		for character in self.world.players()
+		{
+			character::save_character(character);
+		}
+	}
+	
+	pub fn set_link_dead_grace(&mut self, grace: Duration)
+	{
+		self.link_dead_grace = grace;
+	}
+	
+	/// Leave the character of a dropped connection in the world for its player to take back.
+	///
+	/// Returns the room it was left in.
+	pub fn link_dead(&mut self, username: &str) -> Option<String>
+	{
+		let room = match self.world.player(username)
+		{
+			Some(character) =>
+			{
+				//saved now in case the server goes down before it is back
+				character::save_character(character);
+				character.room.clone()
+			},
+			None => return None,
+		};
+		self.link_dead.insert(username.to_string(), Instant::now());
+		Some(room)
+	}
+	
+	/// Every link-dead player and how long ago its connection dropped, for a copyover
+	pub fn link_dead_players(&self) -> Vec<(String, Duration)>
+	{
+		let now = Instant::now();
+		self.link_dead.iter().map(|(username, since)| (username.clone(), now.duration_since(*since))).collect()
+	}
+	
+	/// Put a character back in the world link-dead, as if its connection dropped elapsed ago
+	pub fn restore_link_dead(&mut self, character: Character, elapsed: Duration) -> Result<(), &'static str>
+	{
+		let username = character.username.clone();
+		try!(self.enter_world(character));
+		let now = Instant::now();
+		self.link_dead.insert(username, now.checked_sub(elapsed).unwrap_or(now));
+		Ok(())
+	}
+	
+	/// Hand a link-dead character back to its player, returns the description of its room
+	pub fn reconnect(&mut self, username: &str) -> Option<String>
+	{
+		if self.link_dead.remove(username).is_none()
+		{
+			return None;
+		}
+		self.world.player(username).map(|character| character.room.clone())
+			.map(|room| self.world.describe_room(&room, username))
+	}
+	
+	/// Put a character that enters play in the world, in its saved room or the start room if that is gone.
+	///
+	/// Returns the description of the room it is in, or an error when a character of that name is already playing.
+	pub fn enter_world(&mut self, mut character: Character) -> Result<String, &'static str>
+	{
+		if self.world.get_room(&character.room).is_none()
+		{
+			character.room = self.world.start_room().to_string();
+		}
+		
+		//new characters and ones saved before health was tracked start out healthy
+		let max_hp = combat::Stats::of(&character, &self.world).max_hp;
+		if character.hp <= 0 || character.hp > max_hp
+		{
+			character.hp = max_hp;
+		}
+		let max_mana = skills::max_resource(&character, &self.world, "mana");
+		if character.mana < 0 || character.mana > max_mana
+		{
+			character.mana = max_mana;
+		}
+		let max_stam = skills::max_resource(&character, &self.world, "stam");
+		if character.stam < 0 || character.stam > max_stam
+		{
+			character.stam = max_stam;
+		}
+		character.fighting = None;
 		
-		let inputString = cmd.clone();
-		inputString
+		let username = character.username.clone();
+		let room = character.room.clone();
+		if !self.world.add_player(character)
+		{
+			return Err(connection::ALREADY_IN_WORLD);
+		}
+		Ok(self.world.describe_room(&room, &username))
+	}
+	
+	/// Take a character out of the world and save it
+	pub fn leave_world(&mut self, username: &str) -> Option<Character>
+	{
+		let character = self.world.remove_player(username);
+		if let Some(ref character) = character
+		{
+			character::save_character(character);
+		}
+		character
+	}
+	
+	/// Work done every server tick, returns the messages it produced
+	pub fn tick(&mut self, rng: &mut CombatRng) -> CommandOutput
+	{
+		self.ticks += 1;
+		let mut output = mob::aggression(&mut self.world);
+		output.append(mob::wander(&mut self.world, self.ticks, rng));
+		output.append(combat::combat_round(&mut self.world, rng));
+		skills::tick(&mut self.world, self.ticks);
+		
+		//link-dead characters whose player did not come back in time leave for good
+		for username in limits::expired(&self.link_dead, self.link_dead_grace, Instant::now())
+		{
+			self.link_dead.remove(&username);
+			if let Some(character) = self.leave_world(&username)
+			{
+				output.to_room(&character.room, format!("{} fades away.\r\n", character.username));
+			}
+		}
+		
+		if self.ticks % RESET_TICKS == 0
+		{
+			self.world.reset();
+		}
+		if self.ticks % SAVE_TICKS == 0
+		{
+			self.save_all();
+		}
+		output
+	}
+	
+	pub fn process_commands(&mut self, cmd:String, username: String, channels: &mut Vec<String>, colour: &mut ColourPreference, rng: &mut CombatRng) -> CommandOutput
+	{
+		let admin = self.is_admin(&username);
+		let mut context = CommandContext
+		{
+			username: username,
+			channels: channels,
+			colour: colour,
+			commands: &self.commands,
+			world: &mut self.world,
+			admin: admin,
+			stats: &self.stats,
+			shutdown: &mut self.shutdown_request,
+			rng: rng,
+		};
+		self.commands.dispatch(&mut context, &cmd)
 	}	
-}
\ No newline at end of file
+}
+
+//every gameplay command is registered here
+fn register_commands(commands: &mut CommandRegistry)
+{
+	commands.register(Command::new("say", say).alias("'")
+		.arg(ArgSpec::text("message"))
+		.help("Talk to everyone in the room"));
+	commands.register(Command::new("tell", tell)
+		.arg(ArgSpec::word("name"))
+		.arg(ArgSpec::text("message"))
+		.help("Talk to one player wherever they are"));
+	commands.register(Command::new("shout", shout)
+		.arg(ArgSpec::text("message"))
+		.help("Talk to everyone in the game"));
+	commands.register(Command::new("channel", channel).alias("channels")
+		.arg(ArgSpec::word("name").optional())
+		.arg(ArgSpec::word("on|off").optional())
+		.help("List channels or turn one on or off"));
+	for name in CHANNELS.iter()
+	{
+		commands.register(Command::new(name, channel_talk)
+			.arg(ArgSpec::text("message"))
+			.help("Talk on a channel"));
+	}
+	commands.register(Command::new("look", look).alias("l")
+		.help("Look around the room"));
+	for direction in DIRECTIONS.iter()
+	{
+		commands.register(Command::new(direction.name(), walk).alias(direction.short_name())
+			.help("Walk through an exit"));
+	}
+	commands.register(Command::new("score", score).alias("sc")
+		.help("Show your attributes"));
+	commands.register(Command::new("help", help).alias("commands")
+		.help("List the commands"));
+	commands.register(Command::new("connections", connections).admin()
+		.help("Show connection counts and how many were turned away"));
+	commands.register(Command::new("shutdown", shutdown).admin()
+		.arg(ArgSpec::number("seconds").optional())
+		.help("Warn everyone, then save and stop the server"));
+	commands.register(Command::new("copyover", copyover).admin()
+		.help("Restart on the newest build without disconnecting anyone"));
+}
+
+fn say(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let message = args.text(0).unwrap_or("");
+	let mut output = CommandOutput::reply(format!("You say '{}'\r\n", message));
+	output.to_room(&context.character().room, format!("{} says '{}'\r\n", context.username, message));
+	output
+}
+
+fn tell(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let target = match context.world.find_player(args.word(0).unwrap_or(""))
+	{
+		Some(character) => character.username.clone(),
+		None => return CommandOutput::reply(game::NO_SUCH_PLAYER.to_string()),
+	};
+	let message = args.text(1).unwrap_or("");
+	let mut output = CommandOutput::reply(format!("You tell {} '{}'\r\n", target, message));
+	output.to_user(&target, format!("{} tells you '{}'\r\n", context.username, message));
+	output
+}
+
+fn shout(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let message = args.text(0).unwrap_or("");
+	let mut output = CommandOutput::reply(format!("You shout '{}'\r\n", message));
+	output.to_all(format!("{} shouts '{}'\r\n", context.username, message));
+	output
+}
+
+fn channel_talk(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let channel = args.command;
+	if !context.channels.contains(&channel.to_string())
+	{
+		return CommandOutput::reply(format!("You are not on the {} channel, use 'channel {} on' to join\r\n", channel, channel));
+	}
+	
+	let text = format!("[{}] {}: {}\r\n", channel, context.username, args.text(0).unwrap_or(""));
+	let mut output = CommandOutput::reply(text.clone());
+	output.to_channel(channel, text);
+	output
+}
+
+//list channels, or turn one on or off
+fn channel(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let name = match args.word(0)
+	{
+		Some(name) => name.to_lowercase(),
+		None =>
+		{
+			let mut list = String::new();
+			for channel in CHANNELS.iter()
+			{
+				let status = if context.channels.contains(&channel.to_string()) { "on" } else { "off" };
+				list = list + channel + ": " + status + "\r\n";
+			}
+			return CommandOutput::reply(list);
+		}
+	};
+	
+	if !CHANNELS.contains(&&name[..])
+	{
+		return CommandOutput::reply(format!("There is no {} channel\r\n", name));
+	}
+	
+	match &args.word(1).unwrap_or("").to_lowercase()[..]
+	{
+		"on" =>
+		{
+			if !context.channels.contains(&name)
+			{
+				context.channels.push(name.clone());
+			}
+			CommandOutput::reply(format!("You are now listening to {}\r\n", name))
+		},
+		"off" =>
+		{
+			context.channels.retain(|c| *c != name);
+			CommandOutput::reply(format!("You are no longer listening to {}\r\n", name))
+		},
+		_ => CommandOutput::reply(game::CHANNEL_USAGE.to_string()),
+	}
+}
+
+fn look(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	let room = context.character().room.clone();
+	match context.world.get_room(&room)
+	{
+		Some(_) => CommandOutput::reply(context.world.describe_room(&room, &context.username)),
+		None => CommandOutput::reply(game::NOWHERE.to_string()),
+	}
+}
+
+//the command name is the direction, so one handler serves every exit
+fn walk(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let direction = match Direction::parse(args.command)
+	{
+		Some(direction) => direction,
+		None => return CommandOutput::reply(game::NO_EXIT.to_string()),
+	};
+	
+	if context.character().fighting.is_some()
+	{
+		return CommandOutput::reply(game::STILL_FIGHTING.to_string());
+	}
+	
+	let from = context.character().room.clone();
+	let to = match context.world.follow_exit(&from, direction)
+	{
+		Some(room) => room.id.clone(),
+		None => return CommandOutput::reply(game::NO_EXIT.to_string()),
+	};
+	
+	context.character_mut().room = to.clone();
+	
+	let mut output = CommandOutput::reply(context.world.describe_room(&to, &context.username));
+	output.to_room(&from, format!("{} leaves {}.\r\n", context.username, direction.name()));
+	output.to_room(&to, format!("{} arrives from {}.\r\n", context.username, direction.arrival()));
+	output
+}
+
+//effective values include equipment, base values are what was rolled
+fn score(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	let character = context.character();
+	let effective = equipment::effective_attributes(character, context.world);
+	
+	let mut text = format!("{}, level {}\r\n", character.username, character.level);
+	for name in ATTRIBUTES.iter()
+	{
+		let base = character.base_attr(name);
+		let value = effective.get(*name).cloned().unwrap_or(base);
+		text = text + &format!("  {:<6} {:>4}  (base {})\r\n", name, value, base);
+	}
+	text = text + &format!("Hp: {}/{}  Mana: {}/{}  Stam: {}/{}  Practices: {}\r\n",
+		character.hp, effective.get("hp").cloned().unwrap_or(0),
+		character.mana, effective.get("mana").cloned().unwrap_or(0),
+		character.stam, effective.get("stam").cloned().unwrap_or(0),
+		character.practices);
+	text = text + &format!("Experience: {}  Next level: {}  Training points: {}\r\n",
+		character.xp, experience::xp_for_next(character.level), character.trains);
+	CommandOutput::reply(text)
+}
+
+//the server runs the countdown, a new request restarts it
+fn shutdown(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let seconds = args.number(0).map(|n| n.max(0) as u64).unwrap_or(shutdown::DEFAULT_COUNTDOWN);
+	*context.shutdown = Some(Request::Shutdown(seconds));
+	CommandOutput::reply(format!("Shutting down in {} seconds.\r\n", seconds))
+}
+
+fn copyover(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	*context.shutdown = Some(Request::Copyover);
+	CommandOutput::reply("Starting a copyover.\r\n".to_string())
+}
+
+fn connections(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	let stats = context.stats;
+	CommandOutput::reply(format!("Connections: {}/{}, at most {} per address\r\nRejected: {} while full, {} over the per-address limit\r\n",
+		stats.connections, stats.max_connections, stats.max_per_ip, stats.rejected_full, stats.rejected_per_ip))
+}
+
+fn help(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	let mut text = String::new();
+	for command in context.commands.commands().iter().filter(|c| context.admin || !c.admin)
+	{
+		text = text + &format!("{:<30} {}\r\n", command.usage(), command.help);
+	}
+	CommandOutput::reply(text)
+}
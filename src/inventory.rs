@@ -0,0 +1,283 @@
+// Commands for carrying, dropping, handing over and looking at items
+
+use character;
+use commands::{Args, ArgSpec, Command, CommandOutput, CommandRegistry};
+use gamehandler::CommandContext;
+use world::capitalize;
+use Messages::game;
+
+pub fn register_commands(commands: &mut CommandRegistry)
+{
+	commands.register(Command::new("inventory", inventory).alias("i")
+		.help("List what you are carrying"));
+	commands.register(Command::new("get", get).alias("take")
+		.arg(ArgSpec::word("item"))
+		.help("Pick up an item from the floor"));
+	commands.register(Command::new("drop", drop)
+		.arg(ArgSpec::word("item"))
+		.help("Drop an item you carry"));
+	commands.register(Command::new("give", give)
+		.arg(ArgSpec::word("item"))
+		.arg(ArgSpec::word("player"))
+		.help("Hand an item to someone in the room"));
+	commands.register(Command::new("examine", examine).alias("exa")
+		.arg(ArgSpec::word("item"))
+		.help("Look closely at an item you carry or see"));
+}
+
+fn inventory(context: &mut CommandContext, _: &Args) -> CommandOutput
+{
+	let character = context.character();
+	let mut text = String::from("You are carrying:\r\n");
+	if character.items.is_empty()
+	{
+		text = text + "  nothing\r\n";
+	}
+	for item in character.items.iter()
+	{
+		text = text + "  " + &context.world.item_name(item) + "\r\n";
+	}
+	text = text + &format!("Weight: {}/{}\r\n", context.world.carried_weight(character), character.carry_capacity(context.world));
+	CommandOutput::reply(text)
+}
+
+fn get(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let word = args.word(0).unwrap_or("");
+	let room_id = context.character().room.clone();
+
+	let index = match context.world.get_room(&room_id).and_then(|room| context.world.find_item(&room.items, word))
+	{
+		Some(index) => index,
+		None => return CommandOutput::reply(game::NOT_HERE.to_string()),
+	};
+
+	let item = context.world.get_room(&room_id).unwrap().items[index].clone();
+	let (no_take, weight) = match context.world.get_item(&item.prototype)
+	{
+		Some(prototype) => (prototype.has_flag("no_take"), prototype.weight),
+		None => (false, 0),
+	};
+	if no_take
+	{
+		return CommandOutput::reply(game::CANT_TAKE.to_string());
+	}
+
+	let carried = context.world.carried_weight(context.character());
+	if carried + weight > context.character().carry_capacity(context.world)
+	{
+		return CommandOutput::reply(game::TOO_HEAVY.to_string());
+	}
+
+	context.world.get_room_mut(&room_id).unwrap().items.remove(index);
+	context.character_mut().items.push(item.clone());
+	character::save_character(context.character());
+
+	let name = context.world.item_name(&item);
+	let mut output = CommandOutput::reply(format!("You get {}.\r\n", name));
+	output.to_room(&room_id, format!("{} gets {}.\r\n", context.username, name));
+	output
+}
+
+fn drop(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let word = args.word(0).unwrap_or("");
+	let index = match context.world.find_item(&context.character().items, word)
+	{
+		Some(index) => index,
+		None => return CommandOutput::reply(game::NOT_CARRYING.to_string()),
+	};
+
+	let item = context.character().items[index].clone();
+	if context.world.get_item(&item.prototype).map(|p| p.has_flag("no_drop")).unwrap_or(false)
+	{
+		return CommandOutput::reply(game::CANT_DROP.to_string());
+	}
+
+	let room_id = context.character().room.clone();
+	context.character_mut().items.remove(index);
+	if let Some(room) = context.world.get_room_mut(&room_id)
+	{
+		room.items.push(item.clone());
+	}
+	character::save_character(context.character());
+
+	let name = context.world.item_name(&item);
+	let mut output = CommandOutput::reply(format!("You drop {}.\r\n", name));
+	output.to_room(&room_id, format!("{} drops {}.\r\n", context.username, name));
+	output
+}
+
+fn give(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let word = args.word(0).unwrap_or("");
+	let receiver = args.word(1).unwrap_or("");
+
+	let index = match context.world.find_item(&context.character().items, word)
+	{
+		Some(index) => index,
+		None => return CommandOutput::reply(game::NOT_CARRYING.to_string()),
+	};
+	let item = context.character().items[index].clone();
+	let (no_drop, weight) = match context.world.get_item(&item.prototype)
+	{
+		Some(prototype) => (prototype.has_flag("no_drop"), prototype.weight),
+		None => (false, 0),
+	};
+	if no_drop
+	{
+		return CommandOutput::reply(game::CANT_DROP.to_string());
+	}
+
+	let room_id = context.character().room.clone();
+	let receiver_name = match context.world.find_player(receiver)
+	{
+		Some(other) if other.room == room_id && other.username != context.username =>
+		{
+			if context.world.carried_weight(other) + weight > other.carry_capacity(context.world)
+			{
+				return CommandOutput::reply(format!("{} can't carry that much.\r\n", other.username));
+			}
+			other.username.clone()
+		},
+		_ => return CommandOutput::reply(game::NOBODY_HERE.to_string()),
+	};
+
+	context.character_mut().items.remove(index);
+	context.world.player_mut(&receiver_name).unwrap().items.push(item.clone());
+
+	// both sides are saved together so the item never ends up stored twice
+	character::save_character(context.character());
+	character::save_character(context.world.player(&receiver_name).unwrap());
+
+	let name = context.world.item_name(&item);
+	let mut output = CommandOutput::reply(format!("You give {} to {}.\r\n", name, receiver_name));
+	output.to_user(&receiver_name, format!("{} gives you {}.\r\n", context.username, name));
+	output
+}
+
+fn examine(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let word = args.word(0).unwrap_or("");
+	let room_id = context.character().room.clone();
+
+	let mut found = context.world.find_item(&context.character().items, word)
+		.map(|index| context.character().items[index].clone());
+	if found.is_none()
+	{
+		found = context.world.get_room(&room_id)
+			.and_then(|room| context.world.find_item(&room.items, word).map(|index| room.items[index].clone()));
+	}
+
+	let prototype = match found.and_then(|item| context.world.get_item(&item.prototype))
+	{
+		Some(prototype) => prototype,
+		None => return CommandOutput::reply(game::NOT_HERE.to_string()),
+	};
+
+	let mut text = capitalize(&prototype.name) + "\r\n";
+	if !prototype.description.is_empty()
+	{
+		text = text + &prototype.description + "\r\n";
+	}
+	text = text + &format!("Type: {}  Weight: {}  Value: {}\r\n", prototype.item_type.name(), prototype.weight, prototype.value);
+	if !prototype.flags.is_empty()
+	{
+		text = text + &format!("Flags: {}\r\n", prototype.flags.join(" "));
+	}
+	if let Some(slot) = prototype.wear
+	{
+		let modifiers: Vec<String> = prototype.modifiers.iter().map(|&(ref name, value)| format!("{} {:+}", name, value)).collect();
+		text = text + &format!("Worn on: {}  {}\r\n", slot.name(), modifiers.join(" "));
+	}
+	CommandOutput::reply(text)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::rc::Rc;
+	use character::{self, Character};
+	use colour::ColourPreference;
+	use combat;
+	use gamehandler::GameHandler;
+	use item::{Item, ItemPrototype, ItemType};
+	use storage;
+	use world::World;
+	use Messages::game;
+
+	fn new_game() -> GameHandler
+	{
+		storage::set_db(Rc::new(storage::MemoryStorage::new()));
+		let mut world = World::default_world();
+		world.add_item(ItemPrototype
+		{
+			id: "sword".to_string(),
+			name: "a short sword".to_string(),
+			keywords: vec!["sword".to_string()],
+			description: "Nicked along the edge.".to_string(),
+			weight: 5,
+			value: 10,
+			item_type: ItemType::Weapon,
+			flags: Vec::new(),
+			wear: None,
+			modifiers: Vec::new(),
+		});
+		let start = world.start_room().to_string();
+		world.get_room_mut(&start).unwrap().items.push(Item::new("sword"));
+
+		let mut game = GameHandler::new(world);
+		for name in ["alice", "bob"].iter()
+		{
+			let mut character = Character::new();
+			character.username = name.to_string();
+			game.enter_world(character).unwrap();
+		}
+		game
+	}
+
+	fn run(game: &mut GameHandler, username: &str, cmd: &str) -> String
+	{
+		let mut rng = combat::seeded_rng(1);
+		game.process_commands(cmd.to_string(), username.to_string(), &mut Vec::new(), &mut ColourPreference::new(), &mut rng).reply
+	}
+
+	fn saved_items(username: &str) -> Vec<String>
+	{
+		character::load_character(username.to_string()).map(|character| character.items.into_iter().map(|item| item.prototype).collect()).unwrap_or_default()
+	}
+
+	#[test]
+	fn get_and_drop_move_the_item_and_save()
+	{
+		let mut game = new_game();
+		assert_eq!(run(&mut game, "alice", "get sword"), "You get a short sword.\r\n");
+		assert_eq!(saved_items("alice"), vec!["sword"]);
+		assert!(game.world().get_room(game.world().start_room()).unwrap().items.is_empty());
+
+		assert_eq!(run(&mut game, "alice", "drop sword"), "You drop a short sword.\r\n");
+		assert!(saved_items("alice").is_empty());
+		assert_eq!(game.world().get_room(game.world().start_room()).unwrap().items.len(), 1);
+	}
+
+	#[test]
+	fn give_saves_both_sides()
+	{
+		let mut game = new_game();
+		run(&mut game, "alice", "get sword");
+		assert_eq!(run(&mut game, "alice", "give sword bob"), "You give a short sword to bob.\r\n");
+		assert!(saved_items("alice").is_empty());
+		assert_eq!(saved_items("bob"), vec!["sword"]);
+		assert_eq!(run(&mut game, "alice", "give sword bob"), game::NOT_CARRYING);
+	}
+
+	#[test]
+	fn examine_finds_carried_and_room_items()
+	{
+		let mut game = new_game();
+		assert!(run(&mut game, "alice", "examine sword").starts_with("A short sword\r\nNicked along the edge.\r\n"));
+		run(&mut game, "alice", "get sword");
+		assert!(run(&mut game, "alice", "examine sword").contains("Weight: 5"));
+		assert_eq!(run(&mut game, "bob", "examine sword"), game::NOT_HERE);
+	}
+}
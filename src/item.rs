@@ -0,0 +1,90 @@
+use equipment::WearSlot;
+
+// flags an area file may set on an item
+pub const ITEM_FLAGS: [&'static str; 3] = ["no_take", "no_drop", "glow"];
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ItemType
+{
+	Weapon,
+	Armor,
+	Food,
+	Container,
+	Treasure,
+	Other,
+}
+
+const ITEM_TYPES: [ItemType; 6] = [ItemType::Weapon, ItemType::Armor, ItemType::Food,
+	ItemType::Container, ItemType::Treasure, ItemType::Other];
+
+impl ItemType
+{
+	pub fn name(&self) -> &'static str
+	{
+		match *self
+		{
+			ItemType::Weapon => "weapon",
+			ItemType::Armor => "armor",
+			ItemType::Food => "food",
+			ItemType::Container => "container",
+			ItemType::Treasure => "treasure",
+			ItemType::Other => "other",
+		}
+	}
+
+	pub fn parse(name: &str) -> Option<ItemType>
+	{
+		ITEM_TYPES.iter().find(|t| t.name() == name).cloned()
+	}
+}
+
+/// The template every copy of an item is made from, as written in an area file
+pub struct ItemPrototype
+{
+	pub id: String,
+	pub name: String,
+	// words players can use to refer to the item
+	pub keywords: Vec<String>,
+	pub description: String,
+	pub weight: i32,
+	pub value: i32,
+	pub item_type: ItemType,
+	pub flags: Vec<String>,
+	// where it can be worn, None for items that can't
+	pub wear: Option<WearSlot>,
+	// added to the wearer's attributes
+	pub modifiers: Vec<(String, i32)>,
+}
+
+impl ItemPrototype
+{
+	pub fn has_flag(&self, flag: &str) -> bool
+	{
+		self.flags.iter().any(|f| f == flag)
+	}
+
+	/// True when a word typed by a player refers to this item, a keyword prefix is enough
+	pub fn matches(&self, word: &str) -> bool
+	{
+		let word = word.to_lowercase();
+		!word.is_empty() && self.keywords.iter().any(|keyword| keyword.starts_with(&word[..]))
+	}
+}
+
+/// One copy of an item, carried by a character or lying in a room
+#[derive(Clone,Debug,PartialEq)]
+pub struct Item
+{
+	pub prototype: String,
+}
+
+impl Item
+{
+	pub fn new(prototype: &str) -> Item
+	{
+		Item
+		{
+			prototype: prototype.to_string(),
+		}
+	}
+}
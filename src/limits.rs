@@ -0,0 +1,114 @@
+// Connection limits
+// The decisions behind turning new clients away and dropping idle or link-dead sessions, kept
+// apart from the sockets so they can be tested.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use connection::ConnectionState;
+
+/// Why a new client is turned away
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Rejection
+{
+	// max_connections are connected already
+	Full,
+	// max_per_ip are connected from its address already
+	TooManyFromAddress,
+}
+
+/// Whether one more client fits next to connections clients, from_address of them from its address
+pub fn admit(connections: usize, from_address: usize, max_connections: usize, max_per_ip: usize) -> Result<(), Rejection>
+{
+	if connections >= max_connections
+	{
+		return Err(Rejection::Full);
+	}
+	if from_address >= max_per_ip
+	{
+		return Err(Rejection::TooManyFromAddress);
+	}
+	Ok(())
+}
+
+/// How long a connection may go without input before it is dropped, for each state
+pub struct IdleTimeouts
+{
+	// logging on, or not even sending anything
+	pub logon: Duration,
+	pub creation: Duration,
+	pub play: Duration,
+}
+
+impl IdleTimeouts
+{
+	/// True when a connection in state that last sent something at last_input has been idle too long
+	pub fn is_idle(&self, state: ConnectionState, last_input: Instant, now: Instant) -> bool
+	{
+		let timeout = match state
+		{
+			ConnectionState::New | ConnectionState::Logon => self.logon,
+			ConnectionState::CharacterCreation => self.creation,
+			ConnectionState::Play => self.play,
+		};
+		now.duration_since(last_input) >= timeout
+	}
+}
+
+/// Players whose character has been link-dead since the given time for at least grace, in name order
+pub fn expired(link_dead: &HashMap<String, Instant>, grace: Duration, now: Instant) -> Vec<String>
+{
+	let mut expired: Vec<String> = link_dead.iter()
+		.filter(|&(_, since)| now.duration_since(*since) >= grace)
+		.map(|(username, _)| username.clone())
+		.collect();
+	expired.sort();
+	expired
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn admits_up_to_the_limits()
+	{
+		assert_eq!(admit(0, 0, 10, 2), Ok(()));
+		assert_eq!(admit(9, 1, 10, 2), Ok(()));
+		assert_eq!(admit(10, 0, 10, 2), Err(Rejection::Full));
+		assert_eq!(admit(5, 2, 10, 2), Err(Rejection::TooManyFromAddress));
+		// a full server says so, whatever the address
+		assert_eq!(admit(10, 2, 10, 2), Err(Rejection::Full));
+	}
+
+	#[test]
+	fn idle_timeouts_follow_the_state()
+	{
+		let timeouts = IdleTimeouts { logon: Duration::from_secs(60), creation: Duration::from_secs(300), play: Duration::from_secs(1800) };
+		let start = Instant::now();
+		let after = |secs| start + Duration::from_secs(secs);
+
+		assert!(!timeouts.is_idle(ConnectionState::New, start, after(59)));
+		assert!(timeouts.is_idle(ConnectionState::Logon, start, after(60)));
+		assert!(!timeouts.is_idle(ConnectionState::CharacterCreation, start, after(60)));
+		assert!(timeouts.is_idle(ConnectionState::CharacterCreation, start, after(300)));
+		assert!(!timeouts.is_idle(ConnectionState::Play, start, after(1799)));
+		assert!(timeouts.is_idle(ConnectionState::Play, start, after(1800)));
+	}
+
+	#[test]
+	fn link_dead_players_expire_after_the_grace()
+	{
+		let start = Instant::now();
+		let mut link_dead = HashMap::new();
+		link_dead.insert("bob".to_string(), start);
+		link_dead.insert("Alice".to_string(), start);
+		link_dead.insert("carol".to_string(), start + Duration::from_secs(200));
+		let grace = Duration::from_secs(300);
+
+		assert!(expired(&link_dead, grace, start + Duration::from_secs(299)).is_empty());
+		assert_eq!(expired(&link_dead, grace, start + Duration::from_secs(300)), vec!["Alice".to_string(), "bob".to_string()]);
+		assert_eq!(expired(&link_dead, grace, start + Duration::from_secs(500)).len(), 3);
+	}
+}
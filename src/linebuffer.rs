@@ -0,0 +1,127 @@
+// Line oriented input framing
+// Bytes from the socket are collected until a CR, LF or CRLF ends the line.
+
+pub const MAX_LINE_LENGTH: usize = 1024;
+
+const CR: u8 = 13;
+const LF: u8 = 10;
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Line
+{
+	Complete(String),
+	// the line went over the maximum length and was thrown away
+	TooLong,
+}
+
+pub struct LineBuffer
+{
+	buffer: Vec<u8>,
+	max_length: usize,
+	// a CR ended the previous line, so a following LF belongs to it
+	last_was_cr: bool,
+	// dropping bytes until the end of an over long line
+	discarding: bool,
+}
+
+impl LineBuffer
+{
+	pub fn new(max_length: usize) -> LineBuffer
+	{
+		LineBuffer
+		{
+			buffer: Vec::new(),
+			max_length: max_length,
+			last_was_cr: false,
+			discarding: false,
+		}
+	}
+
+	/// Append bytes and return every line they complete.
+	///
+	/// A partial line stays buffered until the rest of it arrives.
+	pub fn push(&mut self, data: &[u8]) -> Vec<Line>
+	{
+		let mut lines = Vec::new();
+
+		for byte in data
+		{
+			let byte = *byte;
+			if byte == LF && self.last_was_cr
+			{
+				self.last_was_cr = false;
+				continue;
+			}
+			self.last_was_cr = byte == CR;
+
+			if byte == CR || byte == LF
+			{
+				if self.discarding
+				{
+					self.discarding = false;
+				}
+				else
+				{
+					let line = String::from_utf8_lossy(&self.buffer).into_owned();
+					lines.push(Line::Complete(line));
+				}
+				self.buffer.clear();
+			}
+			else if !self.discarding
+			{
+				if self.buffer.len() >= self.max_length
+				{
+					self.buffer.clear();
+					self.discarding = true;
+					lines.push(Line::TooLong);
+				}
+				else
+				{
+					self.buffer.push(byte);
+				}
+			}
+		}
+
+		lines
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn complete(text: &str) -> Line
+	{
+		Line::Complete(text.to_string())
+	}
+
+	#[test]
+	fn ends_lines_on_crlf_lf_or_cr()
+	{
+		let mut buffer = LineBuffer::new(MAX_LINE_LENGTH);
+		assert_eq!(buffer.push(b"one\r\ntwo\nthree\rfour\n"), vec![complete("one"), complete("two"), complete("three"), complete("four")]);
+		// an empty line is still a line
+		assert_eq!(buffer.push(b"\r\n"), vec![complete("")]);
+	}
+
+	#[test]
+	fn keeps_partial_lines_between_reads()
+	{
+		let mut buffer = LineBuffer::new(MAX_LINE_LENGTH);
+		assert!(buffer.push(b"lo").is_empty());
+		assert_eq!(buffer.push(b"ok\r"), vec![complete("look")]);
+		// the LF of a CRLF split across reads doesn't make an extra line
+		assert_eq!(buffer.push(b"\nsay hi\r\n"), vec![complete("say hi")]);
+	}
+
+	#[test]
+	fn throws_away_overlong_lines()
+	{
+		let mut buffer = LineBuffer::new(4);
+		assert_eq!(buffer.push(b"abcdefg"), vec![Line::TooLong]);
+		// the rest of it is dropped too, up to the end of the line
+		assert!(buffer.push(b"hij").is_empty());
+		assert_eq!(buffer.push(b"\r\nabcd\r\n"), vec![complete("abcd")]);
+	}
+}
@@ -1,4 +1,6 @@
 use storage;
+use password;
+use std::io::{Error, ErrorKind};
 use Messages::greeting;
 
 #[derive(Copy,Clone,Debug)]
@@ -12,21 +14,34 @@ pub enum LogonState
 	RegisterPasswordConfirm,
 	RegisterCreation,
 	
+	//the password was right but the player is already in the game, asks whether to take over
+	Takeover,
+	
+	//too many failed password attempts
+	Disconnect,
 	
 	Done
 }
 
+//number of wrong passwords allowed before the connection is dropped
+pub const MAX_PASSWORD_ATTEMPTS: i32 = 3;
+
+//values of the stage column in the player record
+pub const STAGE_CREATION: &'static str = "creation";
+pub const STAGE_PLAY: &'static str = "play";
+
 pub struct LogonManager
 {
 	pub username: String,
 	pub password: String,
 	pub logon_state: LogonState,
 	pub return_msg: String,
+	pub failed_attempts: i32,
 }
 
 impl LogonManager
 {
-	pub fn new_from_data(user:String, pwd:String, state:LogonState, msg:String) -> LogonManager
+	pub fn new_from_data(user:String, pwd:String, state:LogonState, msg:String, attempts:i32) -> LogonManager
 	{
 		LogonManager
 		{
@@ -34,6 +49,7 @@ impl LogonManager
 			password: pwd,
 			logon_state: state,
 			return_msg: msg,
+			failed_attempts: attempts,
 		}
 	}
 	
@@ -45,6 +61,67 @@ impl LogonManager
 			password: String::new(),
 			logon_state: LogonState::Username,
 			return_msg: String::new(),
+			failed_attempts: 0,
+		}
+	}
+}
+
+//the stored account of a user
+pub struct PlayerRecord
+{
+	pub username: String,
+	pub password: String,
+	pub stage: String,
+}
+
+impl storage::Record for PlayerRecord
+{
+	fn table() -> &'static str
+	{
+		"player"
+	}
+	
+	fn key(&self) -> String
+	{
+		self.username.clone()
+	}
+	
+	fn to_columns(&self) -> Vec<storage::DataColumn>
+	{
+		let mut data:Vec<storage::DataColumn> = Vec::new();
+		data.push(storage::DataColumn::new("password".to_string(), self.password.clone()));
+		data.push(storage::DataColumn::new("stage".to_string(), self.stage.clone()));
+		data
+	}
+	
+	fn from_columns(key: &str, data: Vec<storage::DataColumn>) -> Result<PlayerRecord, Error>
+	{
+		let password = match storage::get_value(&data, "password")
+		{
+			Some(password) => password,
+			None => return Err(Error::new(ErrorKind::InvalidData, format!("User {} has no password", key))),
+		};
+		
+		Ok(PlayerRecord
+		{
+			username: key.to_string(),
+			password: password,
+			//a record without a stage never finished creation
+			stage: storage::get_value(&data, "stage").unwrap_or(STAGE_CREATION.to_string()),
+		})
+	}
+}
+
+fn load_player(username: &str) -> Option<PlayerRecord>
+{
+	let db = storage::get_db();
+	match storage::load::<PlayerRecord>(&*db, username)
+	{
+		Ok(record) => record,
+		Err(e) =>
+		{
+			println!("Failed to read user {}: {}", username, e);
+			None
 		}
 	}
 }
@@ -52,40 +129,102 @@ impl LogonManager
 //check whether user already exists
 fn user_exists(username: String) -> bool
 {
-	let mut db = storage::get_db();
-	return db.entry_exists("player", &username[..]);
+	let db = storage::get_db();
+	return db.exists("player", &username[..]);
+}
+
+//compare the input with the stored password
+fn password_matches(username: String, input: String) -> bool
+{
+	let mut record = match load_player(&username)
+	{
+		Some(record) => record,
+		None => return false,
+	};
+	
+	let (matched, upgraded) = password::check_password(&input, &record.password);
+	if let Some(hashed) = upgraded
+	{
+		//legacy plaintext record, replace it with a hash now that we know the password
+		record.password = hashed;
+		let db = storage::get_db();
+		match storage::save(&*db, &record)
+		{
+			Ok(_) => println!("Upgraded password of user {}", username),
+			Err(e) => println!("Failed to upgrade password of user {}: {}", username, e),
+		}
+	}
+	matched
+}
+
+//read how far the user got, a missing record counts as unfinished creation
+pub fn get_stage(username: String) -> String
+{
+	match load_player(&username)
+	{
+		Some(record) => record.stage,
+		None => STAGE_CREATION.to_string(),
+	}
+}
+
+//update the stage column, keeping the password
+pub fn set_stage(username: String, stage: &str)
+{
+	let mut record = match load_player(&username)
+	{
+		Some(record) => record,
+		None => { println!("Failed to update stage of unknown user {}", username); return; }
+	};
+	
+	record.stage = stage.to_string();
+	
+	let db = storage::get_db();
+	match storage::save(&*db, &record)
+	{
+		Ok(_) => println!("User {} is now at stage {}", username, stage),
+		Err(e) => println!("Failed to update stage of user {}: {}", username, e),
+	}
 }
 
 //save user
 fn save_player(username: String, password: String)
 {
-	let mut db = storage::get_db();
-	let mut data:Vec<storage::DataColumn> = Vec::new();
+	let hashed = match password::hash_password(&password)
+	{
+		Ok(hashed) => hashed,
+		Err(e) => { println!("Failed to hash password for {}: {}", username, e); return; }
+	};
 	
-	data.push(storage::DataColumn::new("password".to_string(), password.clone()));
-	data.push(storage::DataColumn::new("stage".to_string(), "creation".to_string()));
+	let record = PlayerRecord
+	{
+		username: username.clone(),
+		password: hashed,
+		stage: STAGE_CREATION.to_string(),
+	};
 	
-	match db.insert("player", &username[..], data)
+	let db = storage::get_db();
+	match storage::save(&*db, &record)
 	{
 		Ok(_) => println!("Successfully saved user {}|", username),
 		Err(e) => println!("Failed to create user {}", e),
 	}
 }
 
-//process inputs
-pub fn process_commands(cmd:String, logon_data: LogonManager) -> LogonManager
+//process inputs, in_session tells whether the user is playing on another connection
+pub fn process_commands(cmd:String, logon_data: LogonManager, in_session: bool) -> LogonManager
 {		
 	let mut username = logon_data.username;
 	let mut password = logon_data.password;
 	let mut logon_state = logon_data.logon_state;
+	let mut failed_attempts = logon_data.failed_attempts;
 	
 	let mut input_string = String::new();
 	let mut logon_state = logon_state;
 	
-	println!("State is {:?}", logon_state);
 	match logon_state
 	{
 		LogonState::RegisterCreation => {},
+		LogonState::Disconnect => {},
 		LogonState::Done => {},
 		_=>
 		{
@@ -96,7 +235,6 @@ pub fn process_commands(cmd:String, logon_data: LogonManager) -> LogonManager
 	let mut input = input_string.trim().to_string();
 	let mut message:String = String::new();
 	
-	println!("Received command: {} EOF", input);
 	match logon_state
 	{
 		LogonState::New => {
@@ -120,8 +258,53 @@ pub fn process_commands(cmd:String, logon_data: LogonManager) -> LogonManager
 		LogonState::Password =>
 		{
 			//retrive record and compare password
-			logon_state = LogonState::Done;
+			if password_matches(username.clone(), input.clone())
+			{
+				failed_attempts = 0;
+				if in_session
+				{
+					message = greeting::ALREADY_PLAYING.to_string();
+					logon_state = LogonState::Takeover;
+				}
+				else
+				{
+					message = greeting::LOGON_SUCCESS.to_string();
+					logon_state = LogonState::Done;
+				}
+			}
+			else
+			{
+				failed_attempts += 1;
+				println!("Wrong password for {}, attempt {}", username, failed_attempts);
+				if failed_attempts >= MAX_PASSWORD_ATTEMPTS
+				{
+					message = greeting::TOO_MANY_ATTEMPTS.to_string();
+					logon_state = LogonState::Disconnect;
+				}
+				else
+				{
+					message = format!("{}{}{}\r\n{}",
+						greeting::WRONG_PASSWORD,
+						greeting::ATTEMPTS_LEFT,
+						MAX_PASSWORD_ATTEMPTS - failed_attempts,
+						greeting::ENTER_PASSWORD);
+				}
+			}
 		}
+		LogonState::Takeover =>
+		{
+			//the new connection gets the character, the old one is dropped
+			if input == "y" || input == "yes"
+			{
+				message = greeting::LOGON_SUCCESS.to_string();
+				logon_state = LogonState::Done;
+			}
+			else
+			{
+				message = greeting::KEEP_SESSION.to_string();
+				logon_state = LogonState::Disconnect;
+			}
+		},
 		LogonState::RegisterNewUser =>
 		{
 			//checks whether input is y or n
@@ -163,8 +346,55 @@ pub fn process_commands(cmd:String, logon_data: LogonManager) -> LogonManager
 			logon_state = LogonState::Done;
 		},
 		
+		LogonState::Disconnect => {},
 		LogonState::Done => {},
 	}
 
-	LogonManager::new_from_data(username, password, logon_state, message)
+	LogonManager::new_from_data(username, password, logon_state, message, failed_attempts)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::rc::Rc;
+	
+	// a memory storage holding one account, alice with the password secret
+	fn with_alice()
+	{
+		storage::set_db(Rc::new(storage::MemoryStorage::new()));
+		save_player("alice".to_string(), "secret".to_string());
+	}
+	
+	fn enter(logon: LogonManager, input: &str) -> LogonManager
+	{
+		process_commands(input.to_string(), logon, false)
+	}
+	
+	#[test]
+	fn counts_failed_attempts_until_the_limit()
+	{
+		with_alice();
+		let mut logon = enter(LogonManager::new(), "alice");
+		for attempt in 1..MAX_PASSWORD_ATTEMPTS
+		{
+			logon = enter(logon, "wrong");
+			assert_eq!(logon.failed_attempts, attempt);
+			assert!(logon.return_msg.contains(&format!("{}{}", greeting::ATTEMPTS_LEFT, MAX_PASSWORD_ATTEMPTS - attempt)));
+			match logon.logon_state { LogonState::Password => {}, state => panic!("should ask for the password again, not {:?}", state) }
+		}
+		logon = enter(logon, "wrong");
+		assert_eq!(logon.failed_attempts, MAX_PASSWORD_ATTEMPTS);
+		assert_eq!(logon.return_msg, greeting::TOO_MANY_ATTEMPTS);
+		match logon.logon_state { LogonState::Disconnect => {}, state => panic!("should disconnect, not {:?}", state) }
+	}
+	
+	#[test]
+	fn right_password_clears_the_failures()
+	{
+		with_alice();
+		let logon = enter(enter(enter(LogonManager::new(), "alice"), "wrong"), "secret");
+		assert_eq!(logon.failed_attempts, 0);
+		match logon.logon_state { LogonState::Done => {}, state => panic!("should be logged on, not {:?}", state) }
+	}
 }
\ No newline at end of file
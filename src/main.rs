@@ -5,8 +5,14 @@ extern crate bytes;
 
 extern crate env_logger;
 
+use std::env;
 use std::net::SocketAddr;
+use std::process;
 use std::str::FromStr;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
 
 use mio::*;
 use mio::tcp::*;
@@ -21,6 +27,28 @@ mod logon;
 mod gamehandler;
 mod storage;
 mod transactionstorage;
+mod telnet;
+mod colour;
+mod linebuffer;
+mod outbuffer;
+mod password;
+mod sqlitestorage;
+mod messagebus;
+mod commands;
+mod world;
+mod area;
+mod item;
+mod mob;
+mod inventory;
+mod equipment;
+mod combat;
+mod skills;
+mod experience;
+mod config;
+mod shutdown;
+mod limits;
+#[cfg(unix)]
+mod copyover;
 
 macro_rules! info {
     ($fmt:expr) => (print!(concat!($fmt, "\n")));
@@ -41,9 +69,46 @@ fn main() {
     // figure out why something is not working correctly.
     env_logger::init().ok().expect("Failed to init logger");
 
-    let addr: SocketAddr = FromStr::from_str("127.0.0.1:8000")
+    // dev, staging and production differ only in their config file and arguments
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    #[cfg(unix)]
+    let copyover_state = copyover::take_arg(&mut args);
+    let config = match config::from_args(&args) {
+        Ok(config) => config,
+        Err(errors) => {
+            for e in errors.iter() {
+                println!("{}", e);
+            }
+            println!("Invalid configuration, {} errors", errors.len());
+            process::exit(1);
+        }
+    };
+    println!("Effective configuration:\n{}", config);
+
+    let addr: SocketAddr = FromStr::from_str(&config.bind)
         .ok().expect("Failed to parse host:port string");
-    let sock = TcpListener::bind(&addr).ok().expect("Failed to bind address");
+    // after a copyover the listening socket is already open, inherited from the old process
+    #[cfg(unix)]
+    let restored = copyover_state.map(|path| {
+        let state = copyover::State::load(&path).unwrap_or_else(|e| {
+            println!("Failed to restore the copyover, {}", e);
+            process::exit(1);
+        });
+        fs::remove_file(&path).ok();
+        state
+    });
+    #[cfg(not(unix))]
+    let restored: Option<()> = None;
+
+    let sock = match restored {
+        #[cfg(unix)]
+        Some(ref state) => unsafe { TcpListener::from_raw_fd(state.listener) },
+        _ => TcpListener::bind(&addr).ok().expect("Failed to bind address"),
+    };
+
+    let db = storage::open_storage(&config.storage, &config.storage_path)
+        .ok().expect("Failed to open storage");
+    storage::set_db(db);
 
     let mut event_loop = EventLoop::new().ok().expect("Failed to create event loop");
 	
@@ -51,9 +116,16 @@ fn main() {
     // the details of how registering works inside of the `Server#register` function. One reason I
     // really like this is to get around having to have `const SERVER = Token(0)` at the top of my
     // file. It also keeps our polling options inside `Server`.
-    let mut server = server::Server::new(sock);
+    let mut server = server::Server::new(sock, &config);
     server.register(&mut event_loop).ok().expect("Failed to register server with event loop");
+    #[cfg(unix)]
+    if let Some(state) = restored {
+        println!("Restoring {} connections after a copyover", state.connections.len());
+        server.restore(state, &mut event_loop);
+    }
+    shutdown::catch_signals();
 
     info!("Even loop starting...");
     event_loop.run(&mut server).ok().expect("Failed to start event loop");
+    println!("Server stopped");
 }
\ No newline at end of file
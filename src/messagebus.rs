@@ -0,0 +1,133 @@
+extern crate time;
+
+use mio::Token;
+
+/// Who a message is addressed to
+#[derive(Clone,Debug,PartialEq)]
+pub enum Target
+{
+	// global shout
+	All,
+	// direct tell, by username
+	User(String),
+	// everyone in a room
+	Room(String),
+	// everyone subscribed to a named channel
+	Channel(String),
+}
+
+impl Target
+{
+	/// The value stored in the target column of the message log
+	pub fn to_column(&self) -> String
+	{
+		match *self
+		{
+			Target::All => "all".to_string(),
+			Target::User(ref name) => format!("user:{}", name),
+			Target::Room(ref room) => format!("room:{}", room),
+			Target::Channel(ref channel) => format!("channel:{}", channel),
+		}
+	}
+
+	/// Whether a message to this target reaches the player username, standing in room and listening to channels
+	pub fn reaches(&self, username: &str, room: Option<&str>, channels: &[String]) -> bool
+	{
+		match *self
+		{
+			Target::All => true,
+			// account names are exact, "Bob" is somebody else than "bob"
+			Target::User(ref name) => name == username,
+			Target::Room(ref target) => room == Some(&target[..]),
+			Target::Channel(ref channel) => channels.contains(channel),
+		}
+	}
+}
+
+/// A message published by a connection, waiting to be fanned out by `Server`
+pub struct Envelope
+{
+	// connection that published the message, it does not get a copy
+	pub sender: Token,
+	pub target: Target,
+	pub text: String,
+	pub time_created: time::Timespec,
+}
+
+impl Envelope
+{
+	pub fn new(sender: Token, target: Target, text: String) -> Envelope
+	{
+		Envelope
+		{
+			sender: sender,
+			target: target,
+			text: text,
+			time_created: time::get_time(),
+		}
+	}
+}
+
+/// Messages published while handling input, delivered by the server before the next event
+pub struct MessageBus
+{
+	queue: Vec<Envelope>,
+}
+
+impl MessageBus
+{
+	pub fn new() -> MessageBus
+	{
+		MessageBus
+		{
+			queue: Vec::new(),
+		}
+	}
+
+	pub fn publish(&mut self, message: Envelope)
+	{
+		self.queue.push(message);
+	}
+
+	/// Take every pending message in the order it was published
+	pub fn drain(&mut self) -> Vec<Envelope>
+	{
+		self.queue.drain(..).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn targets_reach_the_right_players()
+	{
+		let channels = vec!["ooc".to_string()];
+		let reaches = |target: Target| target.reaches("Alice", Some("square"), &channels);
+
+		assert!(reaches(Target::All));
+		assert!(reaches(Target::User("Alice".to_string())));
+		assert!(!reaches(Target::User("alice".to_string())));
+		assert!(!reaches(Target::User("Bob".to_string())));
+		assert!(reaches(Target::Room("square".to_string())));
+		assert!(!reaches(Target::Room("Square".to_string())));
+		assert!(reaches(Target::Channel("ooc".to_string())));
+		assert!(!reaches(Target::Channel("gossip".to_string())));
+		// somebody not in the world yet hears no room
+		assert!(!Target::Room("square".to_string()).reaches("Alice", None, &channels));
+	}
+
+	#[test]
+	fn drains_in_publish_order()
+	{
+		let mut bus = MessageBus::new();
+		bus.publish(Envelope::new(Token(1), Target::All, "first".to_string()));
+		bus.publish(Envelope::new(Token(2), Target::Channel("ooc".to_string()), "second".to_string()));
+		let texts: Vec<String> = bus.drain().into_iter().map(|message| message.text).collect();
+		assert_eq!(texts, vec!["first".to_string(), "second".to_string()]);
+		assert!(bus.drain().is_empty());
+		assert_eq!(Target::User("Alice".to_string()).to_column(), "user:Alice");
+	}
+}
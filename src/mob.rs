@@ -0,0 +1,356 @@
+// Mobs
+// Prototypes come from the area files, the resets spawn copies of them into rooms. Spawned mobs
+// are indexed by room, by the reset that made them and by wander bucket, so a tick only touches
+// the mobs that have something to do instead of walking every mob in the world.
+
+extern crate rand;
+
+use std::collections::{BTreeSet, HashMap};
+
+use self::rand::Rng;
+
+use character::Character;
+use combat::Combatant;
+use commands::CommandOutput;
+use world::{World, DIRECTIONS};
+
+// behaviours an area file may give a mob
+pub const BEHAVIOURS: [&'static str; 3] = ["wander", "aggressive", "shopkeeper"];
+
+// wandering mobs are split over this many ticks, each gets a chance to move once per cycle
+pub const WANDER_BUCKETS: usize = 8;
+
+// percent chance a wandering mob moves when its bucket comes up
+const WANDER_CHANCE: u32 = 30;
+
+/// The template every copy of a mob is made from, as written in an area file
+pub struct MobPrototype
+{
+	pub id: String,
+	pub name: String,
+	// words players can use to refer to the mob
+	pub keywords: Vec<String>,
+	pub description: String,
+	// base attributes, anything left out starts where a new player's would
+	pub attributes: Vec<(String, i32)>,
+	pub behaviours: Vec<String>,
+}
+
+impl MobPrototype
+{
+	pub fn has_behaviour(&self, behaviour: &str) -> bool
+	{
+		self.behaviours.iter().any(|b| b == behaviour)
+	}
+
+	/// True when a word typed by a player refers to this mob, a keyword prefix is enough
+	pub fn matches(&self, word: &str) -> bool
+	{
+		let word = word.to_lowercase();
+		!word.is_empty() && self.keywords.iter().any(|keyword| keyword.starts_with(&word[..]))
+	}
+}
+
+/// One spawned copy of a prototype, its stats and fight live in its character
+pub struct Mob
+{
+	pub prototype: String,
+	pub character: Character,
+	// the area it may wander in
+	pub area: String,
+	// the reset that spawned it, so the reset knows to replace it once it dies
+	pub reset: Option<usize>,
+}
+
+/// Every spawned mob, with the indexes the tick needs
+pub struct Population
+{
+	mobs: HashMap<u32, Mob>,
+	by_room: HashMap<String, Vec<u32>>,
+	per_reset: HashMap<usize, u32>,
+	// may hold mobs that stopped fighting, they are dropped when the list is read
+	fighting: BTreeSet<u32>,
+	wanderers: Vec<Vec<u32>>,
+	next_id: u32,
+}
+
+impl Population
+{
+	pub fn new() -> Population
+	{
+		Population
+		{
+			mobs: HashMap::new(),
+			by_room: HashMap::new(),
+			per_reset: HashMap::new(),
+			fighting: BTreeSet::new(),
+			wanderers: vec![Vec::new(); WANDER_BUCKETS],
+			next_id: 1,
+		}
+	}
+
+	pub fn spawn(&mut self, prototype: &MobPrototype, room: &str, area: &str, reset: Option<usize>) -> u32
+	{
+		let id = self.next_id;
+		self.next_id += 1;
+
+		let mut character = Character::new();
+		character.username = prototype.name.clone();
+		character.room = room.to_string();
+		for &(ref name, value) in prototype.attributes.iter()
+		{
+			character.attr.insert(name.clone(), value);
+		}
+		character.hp = character.base_attr("hp");
+
+		// shopkeepers mind their shop even if they were also told to wander
+		if prototype.has_behaviour("wander") && !prototype.has_behaviour("shopkeeper")
+		{
+			self.wanderers[id as usize % WANDER_BUCKETS].push(id);
+		}
+		if let Some(reset) = reset
+		{
+			*self.per_reset.entry(reset).or_insert(0) += 1;
+		}
+		self.by_room.entry(room.to_string()).or_insert(Vec::new()).push(id);
+
+		self.mobs.insert(id, Mob
+		{
+			prototype: prototype.id.clone(),
+			character: character,
+			area: area.to_string(),
+			reset: reset,
+		});
+		id
+	}
+
+	pub fn remove(&mut self, id: u32) -> Option<Mob>
+	{
+		let mob = match self.mobs.remove(&id)
+		{
+			Some(mob) => mob,
+			None => return None,
+		};
+
+		if let Some(ids) = self.by_room.get_mut(&mob.character.room)
+		{
+			ids.retain(|other| *other != id);
+		}
+		if let Some(reset) = mob.reset
+		{
+			if let Some(count) = self.per_reset.get_mut(&reset)
+			{
+				*count -= 1;
+			}
+		}
+		self.fighting.remove(&id);
+		Some(mob)
+	}
+
+	pub fn move_to(&mut self, id: u32, room: &str)
+	{
+		let from = match self.mobs.get_mut(&id)
+		{
+			Some(mob) => ::std::mem::replace(&mut mob.character.room, room.to_string()),
+			None => return,
+		};
+
+		if let Some(ids) = self.by_room.get_mut(&from)
+		{
+			ids.retain(|other| *other != id);
+		}
+		self.by_room.entry(room.to_string()).or_insert(Vec::new()).push(id);
+	}
+
+	pub fn get(&self, id: u32) -> Option<&Mob>
+	{
+		self.mobs.get(&id)
+	}
+
+	pub fn get_mut(&mut self, id: u32) -> Option<&mut Mob>
+	{
+		self.mobs.get_mut(&id)
+	}
+
+	/// Ids of the mobs in a room, oldest first
+	pub fn in_room(&self, room: &str) -> Vec<u32>
+	{
+		self.by_room.get(room).cloned().unwrap_or(Vec::new())
+	}
+
+	/// How many mobs spawned by a reset are still alive
+	pub fn alive_from(&self, reset: usize) -> u32
+	{
+		self.per_reset.get(&reset).cloned().unwrap_or(0)
+	}
+
+	pub fn mark_fighting(&mut self, id: u32)
+	{
+		self.fighting.insert(id);
+	}
+
+	/// Mobs that are fighting, lowest id first
+	pub fn fighting(&mut self) -> Vec<u32>
+	{
+		let mobs = &self.mobs;
+		let stopped: Vec<u32> = self.fighting.iter()
+			.filter(|id| mobs.get(id).map(|mob| mob.character.fighting.is_none()).unwrap_or(true))
+			.cloned()
+			.collect();
+		for id in stopped
+		{
+			self.fighting.remove(&id);
+		}
+		self.fighting.iter().cloned().collect()
+	}
+
+	/// The wandering mobs whose turn it is this tick
+	fn wander_bucket(&mut self, tick: u64) -> Vec<u32>
+	{
+		let mobs = &self.mobs;
+		let bucket = &mut self.wanderers[(tick % WANDER_BUCKETS as u64) as usize];
+		bucket.retain(|id| mobs.contains_key(id));
+		bucket.clone()
+	}
+}
+
+/// Let this tick's bucket of wandering mobs move to a neighbouring room of their own area
+pub fn wander<R: Rng>(world: &mut World, tick: u64, rng: &mut R) -> CommandOutput
+{
+	let mut output = CommandOutput::new();
+
+	for id in world.population_mut().wander_bucket(tick)
+	{
+		let (from, area, name) = match world.population().get(id)
+		{
+			Some(mob) if mob.character.fighting.is_none() => (mob.character.room.clone(), mob.area.clone(), mob.character.username.clone()),
+			_ => continue,
+		};
+		if rng.gen_range(0, 100) >= WANDER_CHANCE
+		{
+			continue;
+		}
+
+		let exits: Vec<_> = DIRECTIONS.iter()
+			.filter_map(|direction| world.follow_exit(&from, *direction).map(|room| (*direction, room)))
+			.filter(|&(_, room)| room.area == area)
+			.map(|(direction, room)| (direction, room.id.clone()))
+			.collect();
+		if exits.is_empty()
+		{
+			continue;
+		}
+
+		let (direction, to) = exits[rng.gen_range(0, exits.len())].clone();
+		world.population_mut().move_to(id, &to);
+
+		let name = ::world::capitalize(&name);
+		output.to_room(&from, format!("{} leaves {}.\r\n", name, direction.name()));
+		output.to_room(&to, format!("{} arrives from {}.\r\n", name, direction.arrival()));
+	}
+	output
+}
+
+/// Aggressive mobs attack any player in their room who is not fighting yet.
+///
+/// Only the rooms players stand in are looked at, however many mobs there are.
+pub fn aggression(world: &mut World) -> CommandOutput
+{
+	let mut output = CommandOutput::new();
+
+	let mut players: Vec<(String, String)> = world.players()
+		.filter(|c| c.fighting.is_none())
+		.map(|c| (c.username.clone(), c.room.clone()))
+		.collect();
+	players.sort();
+
+	for (player, room) in players
+	{
+		for id in world.population().in_room(&room)
+		{
+			let aggressive = world.population().get(id)
+				.filter(|mob| mob.character.fighting.is_none())
+				.and_then(|mob| world.get_mob(&mob.prototype))
+				.map(|prototype| prototype.has_behaviour("aggressive") && !prototype.has_behaviour("shopkeeper"))
+				.unwrap_or(false);
+			if !aggressive
+			{
+				continue;
+			}
+
+			let name = {
+				let mob = world.population_mut().get_mut(id).unwrap();
+				mob.character.fighting = Some(Combatant::Player(player.clone()));
+				::world::capitalize(&mob.character.username)
+			};
+			world.population_mut().mark_fighting(id);
+			output.to_room(&room, format!("{} screams and attacks {}!\r\n", name, player));
+			break;
+		}
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use combat::seeded_rng;
+	use world::{Reset, ResetKind, DEFAULT_ROOM};
+
+	fn rat(behaviours: &[&str]) -> MobPrototype
+	{
+		MobPrototype
+		{
+			id: "rat".to_string(),
+			name: "a rat".to_string(),
+			keywords: vec!["rat".to_string()],
+			description: String::new(),
+			attributes: vec![("hp".to_string(), 5)],
+			behaviours: behaviours.iter().map(|b| b.to_string()).collect(),
+		}
+	}
+
+	#[test]
+	fn resets_replace_dead_mobs()
+	{
+		let mut world = World::default_world();
+		world.add_mob(rat(&[]));
+		world.add_reset(Reset { kind: ResetKind::Mob, prototype: "rat".to_string(), room: DEFAULT_ROOM.to_string(), max: 2 });
+
+		world.reset();
+		world.reset();
+		let rats = world.population().in_room(DEFAULT_ROOM);
+		assert_eq!(rats.len(), 2);
+		assert_eq!(world.population().get(rats[0]).unwrap().character.hp, 5);
+		assert_eq!(world.find_mob(DEFAULT_ROOM, "ra"), Some(rats[0]));
+
+		world.population_mut().remove(rats[0]);
+		assert_eq!(world.population().alive_from(0), 1);
+		world.reset();
+		assert_eq!(world.population().in_room(DEFAULT_ROOM).len(), 2);
+	}
+
+	#[test]
+	fn wanderers_stay_in_their_area()
+	{
+		let mut world = World::default_world();
+		world.add_mob(rat(&["wander"]));
+		for room in ["square", "east_street"].iter()
+		{
+			world.get_room_mut(room).unwrap().area = "town".to_string();
+		}
+		world.add_reset(Reset { kind: ResetKind::Mob, prototype: "rat".to_string(), room: "square".to_string(), max: 1 });
+		world.reset();
+
+		let id = world.population().in_room("square")[0];
+		let mut rng = seeded_rng(3);
+		for tick in 0..200
+		{
+			wander(&mut world, tick, &mut rng);
+			let room = world.population().get(id).unwrap().character.room.clone();
+			assert!(room == "square" || room == "east_street");
+			assert_eq!(world.population().in_room(&room), vec![id]);
+		}
+	}
+}
@@ -0,0 +1,168 @@
+// Outbound framing
+// Everything queued for a client is appended to one byte buffer, so messages leave in the order
+// they were sent and small ones go out together in a single write.
+
+use std::io;
+use std::io::{ErrorKind, Write};
+
+// a client with this much unsent output has stopped reading
+pub const HIGH_WATER_MARK: usize = 256 * 1024;
+
+pub struct OutBuffer
+{
+	buffer: Vec<u8>,
+	// bytes before start were already written
+	start: usize,
+	high_water_mark: usize,
+}
+
+impl OutBuffer
+{
+	pub fn new(high_water_mark: usize) -> OutBuffer
+	{
+		OutBuffer
+		{
+			buffer: Vec::new(),
+			start: 0,
+			high_water_mark: high_water_mark,
+		}
+	}
+
+	/// Bytes waiting to be written
+	pub fn len(&self) -> usize
+	{
+		self.buffer.len() - self.start
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.len() == 0
+	}
+
+	/// Queue data behind everything already waiting.
+	///
+	/// Returns false, without queueing it, when that would go over the high-water mark.
+	pub fn push(&mut self, data: &[u8]) -> bool
+	{
+		if self.len() + data.len() > self.high_water_mark
+		{
+			return false;
+		}
+		self.buffer.extend_from_slice(data);
+		true
+	}
+
+	/// Remove and return everything still waiting to be written
+	pub fn take(&mut self) -> Vec<u8>
+	{
+		let rest = self.buffer.split_off(self.start);
+		self.buffer.clear();
+		self.start = 0;
+		rest
+	}
+
+	/// Write until everything is sent or the writer would block, a partial write keeps the rest.
+	///
+	/// Returns how many bytes were written.
+	pub fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<usize>
+	{
+		let mut written = 0;
+		while !self.is_empty()
+		{
+			match writer.write(&self.buffer[self.start..])
+			{
+				Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "client stopped accepting data")),
+				Ok(n) =>
+				{
+					self.start += n;
+					written += n;
+				},
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+				Err(e) => return Err(e),
+			}
+		}
+
+		// drop what was written once it is worth the copy
+		if self.is_empty()
+		{
+			self.buffer.clear();
+			self.start = 0;
+		}
+		else if self.start > self.buffer.len() / 2
+		{
+			self.buffer.drain(..self.start);
+			self.start = 0;
+		}
+		Ok(written)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::io;
+	use std::io::{ErrorKind, Write};
+
+	// takes at most chunk bytes per write and blocks once it holds limit bytes
+	struct Socket
+	{
+		received: Vec<u8>,
+		chunk: usize,
+		limit: usize,
+	}
+
+	impl Write for Socket
+	{
+		fn write(&mut self, data: &[u8]) -> io::Result<usize>
+		{
+			let room = self.limit - self.received.len();
+			if room == 0
+			{
+				return Err(io::Error::new(ErrorKind::WouldBlock, "full"));
+			}
+			let n = data.len().min(self.chunk).min(room);
+			self.received.extend_from_slice(&data[..n]);
+			Ok(n)
+		}
+
+		fn flush(&mut self) -> io::Result<()>
+		{
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn keeps_order_across_partial_writes()
+	{
+		let mut buffer = OutBuffer::new(HIGH_WATER_MARK);
+		let mut socket = Socket { received: Vec::new(), chunk: 3, limit: 7 };
+		assert!(buffer.push(b"first "));
+		assert!(buffer.push(b"second "));
+
+		assert_eq!(buffer.write_to(&mut socket).unwrap(), 7);
+		assert_eq!(buffer.len(), 6);
+		assert!(buffer.push(b"third"));
+
+		socket.limit = 100;
+		buffer.write_to(&mut socket).unwrap();
+		assert!(buffer.is_empty());
+		assert_eq!(&socket.received[..], &b"first second third"[..]);
+
+		socket.limit = 20;
+		assert!(buffer.push(b"fourth"));
+		buffer.write_to(&mut socket).unwrap();
+		assert_eq!(buffer.take(), b"urth".to_vec());
+		assert!(buffer.is_empty());
+	}
+
+	#[test]
+	fn refuses_past_the_high_water_mark()
+	{
+		let mut buffer = OutBuffer::new(10);
+		assert!(buffer.push(b"0123456789"));
+		assert!(!buffer.push(b"x"));
+		assert_eq!(buffer.len(), 10);
+	}
+}
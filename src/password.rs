@@ -0,0 +1,101 @@
+extern crate crypto;
+
+use self::crypto::pbkdf2;
+
+// PBKDF2-HMAC-SHA256 rounds for new hashes
+pub const HASH_ITERATIONS: u32 = 10000;
+
+// Hashes are stored as $rpbkdf2$0$<iterations>$<salt>$<hash>$, the salt and
+// iteration count travel with the hash so they can be changed later
+const HASH_PREFIX: &'static str = "$rpbkdf2$";
+
+/// Hash a password with a random salt
+pub fn hash_password(password: &str) -> Result<String, String>
+{
+	pbkdf2::pbkdf2_simple(password, HASH_ITERATIONS).map_err(|e| e.to_string())
+}
+
+/// True when the stored value is a hash rather than a legacy plaintext password
+pub fn is_hashed(stored: &str) -> bool
+{
+	stored.starts_with(HASH_PREFIX)
+}
+
+/// Compare a password with a stored hash
+pub fn verify_password(password: &str, stored: &str) -> bool
+{
+	match pbkdf2::pbkdf2_check(password, stored)
+	{
+		Ok(matched) => matched,
+		Err(e) =>
+		{
+			println!("Malformed password hash: {}", e);
+			false
+		}
+	}
+}
+
+/// Check a password against a stored hash or legacy plaintext password.
+///
+/// Returns whether it matched, and for a matching plaintext record the hash to store in its place.
+pub fn check_password(password: &str, stored: &str) -> (bool, Option<String>)
+{
+	if is_hashed(stored)
+	{
+		return (verify_password(password, stored), None);
+	}
+	if stored != password
+	{
+		return (false, None);
+	}
+	match hash_password(password)
+	{
+		Ok(hashed) => (true, Some(hashed)),
+		Err(e) =>
+		{
+			// still lets the player in, the upgrade is tried again next time
+			println!("Failed to hash password: {}", e);
+			(true, None)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn hashes_verify_and_reject_wrong_passwords()
+	{
+		let hashed = hash_password("hunter2").unwrap();
+		assert!(verify_password("hunter2", &hashed));
+		assert!(!verify_password("hunter3", &hashed));
+		assert!(!verify_password("", &hashed));
+		// a fresh salt every time
+		assert!(hashed != hash_password("hunter2").unwrap());
+	}
+
+	#[test]
+	fn recognises_the_hash_format()
+	{
+		let hashed = hash_password("secret").unwrap();
+		assert!(hashed.starts_with(HASH_PREFIX) && hashed.ends_with("$"));
+		assert!(is_hashed(&hashed));
+		assert!(!is_hashed("secret"));
+		assert!(!verify_password("secret", "$rpbkdf2$0$garbage$"));
+	}
+
+	#[test]
+	fn upgrades_legacy_plaintext()
+	{
+		let (matched, upgraded) = check_password("secret", "secret");
+		assert!(matched);
+		let hashed = upgraded.expect("a plaintext record should be upgraded");
+		assert!(is_hashed(&hashed) && verify_password("secret", &hashed));
+
+		assert_eq!(check_password("wrong", "secret"), (false, None));
+		assert_eq!(check_password("secret", &hashed), (true, None));
+		assert_eq!(check_password("wrong", &hashed), (false, None));
+	}
+}
@@ -1,21 +1,42 @@
 use connection;
-use bytes::{Buf, RingBuf, SliceBuf, MutBuf, ByteBuf};
 
 use std::io;
-use std::io::{Error, ErrorKind};
+use std::io::Write;
 use std::net::SocketAddr;
-use std::str::FromStr;
+use std::process;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use mio::*;
 use mio::tcp::*;
 use mio::util::Slab;
 
-use macros;
-
 use transactionstorage;
-use transactionstorage::SqliteDB;
 use transactionstorage::Transaction;
 
+use messagebus::{Envelope, MessageBus, Target};
+
+use gamehandler::GameHandler;
+
+use area;
+#[cfg(unix)]
+use character;
+use combat;
+use config::Config;
+use limits;
+use limits::{IdleTimeouts, Rejection};
+use shutdown;
+use shutdown::Request;
+#[cfg(unix)]
+use copyover;
+
+use Messages::game;
+use Messages::connection as connection_messages;
+
 pub struct Server {
     // main socket for our server
     sock: TcpListener,
@@ -26,26 +47,65 @@ pub struct Server {
     // a list of connections _accepted_ by our server
     conns: Slab<connection::Connection>,
 
-	db: transactionstorage::SqliteDB
+	// messages published by connections, fanned out after each read
+	bus: MessageBus,
+
+	// history of every delivered message, written in the background
+	log: transactionstorage::TransactionLog,
+	log_db: String,
+
+	// commands available to playing connections
+	game: GameHandler,
+
+	// dice for combat rounds
+	rng: combat::CombatRng,
+
+	// time between ticks and the token of the timer that runs them
+	tick_ms: u64,
+	timer_token: u32,
+
+	// the slab starts with room for slab_size connections and grows by as much when full,
+	// up to max_connections
+	slab_size: usize,
+	slab_capacity: usize,
+	max_connections: usize,
+	max_per_ip: usize,
+
+	// connections that send nothing for this long are dropped
+	timeouts: IdleTimeouts,
+
+	// set once a shutdown was asked for, new clients are turned away while it counts down
+	shutdown: Option<shutdown::Countdown>,
 }
 
 impl Handler for Server {
     type Timeout = u32;
     type Message = ();
 
-	fn timeout(&mut self, event_loop: &mut EventLoop<Server>, timeout: Self::Timeout) {
-	 // Queue up a write for all connected clients.
-        for conn in self.conns.iter_mut() {
-        // println!("Tick: {:?}", conn.token);
-			conn.handle_heartbeat();
-        }
-		event_loop.timeout_ms(123, 1000).unwrap();
+	fn timeout(&mut self, event_loop: &mut EventLoop<Server>, _timeout: Self::Timeout) {
+		event_loop.timeout_ms(self.timer_token, self.tick_ms).unwrap();
+
+		// every tick is one combat round
+		let output = self.game.tick(&mut self.rng);
+		for (target, text) in output.messages {
+			self.bus.publish(Envelope::new(self.token, target, text));
+		}
+		self.deliver_messages(event_loop);
+		self.drop_idle(event_loop);
+		self.count_down(event_loop);
 	}
 	
     fn ready(&mut self, event_loop: &mut EventLoop<Server>, token: Token, events: EventSet) {
         info!("events = {:?}", events);
         assert!(token != Token(0), "[BUG]: Received event for Token(0)");
 
+        // Delivering messages, a takeover or a failed write can reset connections other than the
+        // one an event is for, so an event may still arrive for a token that is gone.
+        if self.token != token && !self.conns.contains(token) {
+            info!("Event for reset connection {:?}", token);
+            return;
+        }
+
         if events.is_error() {
             println!("Error event for {:?}", token);
             self.reset_connection(event_loop, token);
@@ -78,24 +138,51 @@ impl Handler for Server {
             info!("Read event for {:?}", token);
             if self.token == token {
                 self.accept(event_loop);
+            } else if !self.conns.contains(token) {
+                // the write above failed and reset it
+                return;
             } else {
-
+                // handling the input can take over or reset this very connection too
                 self.readable(event_loop, token)
-                    .and_then(|_| self.find_connection_by_token(token).reregister(event_loop))
+                    .and_then(|_| match self.conns.get_mut(token) {
+                        Some(conn) => conn.reregister(event_loop),
+                        None => Ok(()),
+                    })
                     .unwrap_or_else(|e| {
                         println!("Read event failed for {:?}: {:?}", token, e);
                         self.reset_connection(event_loop, token);
                     });
             }
         }
+
+        // A connection that asked to be closed is dropped once its last message went out.
+        if self.token != token && self.conns.contains(token) && self.find_connection_by_token(token).is_closed() {
+            self.reset_connection(event_loop, token);
+        }
     }
 }
 
 impl Server {
-    pub fn new(sock: TcpListener) -> Server {
-		let sqlite = transactionstorage::SqliteDB::new("db/messages.db");
-		let dbCopy = sqlite.clone();
-		sqlite.createDB();
+    pub fn new(sock: TcpListener, config: &Config) -> Server {
+		let log = transactionstorage::TransactionLog::new(&config.log_db);
+
+		// builders need every mistake listed, so the server refuses to start on any of them
+		let world = match area::load_areas(&config.areas) {
+			Ok(world) => world,
+			Err(errors) => {
+				for e in errors.iter() {
+					println!("{}", e);
+				}
+				println!("Failed to load areas, {} errors", errors.len());
+				process::exit(1);
+			}
+		};
+
+		let mut game = GameHandler::new(world);
+		game.set_admins(config.admins.clone());
+		game.stats_mut().max_connections = config.max_connections;
+		game.stats_mut().max_per_ip = config.max_per_ip;
+		game.set_link_dead_grace(Duration::from_secs(config.linkdead_timeout));
 		
         Server {
             sock: sock,
@@ -106,11 +193,26 @@ impl Server {
             // going on.
             token: Token(1),
 			
-			db: dbCopy,
+			bus: MessageBus::new(),
+			log: log,
+			log_db: config.log_db.clone(),
+			game: game,
+			rng: combat::new_rng(),
+			tick_ms: config.tick_ms,
+			timer_token: config.timer_token,
+			slab_size: config.slab_size,
+			slab_capacity: config.slab_size,
+			max_connections: config.max_connections,
+			max_per_ip: config.max_per_ip,
+			timeouts: IdleTimeouts {
+				logon: Duration::from_secs(config.logon_timeout),
+				creation: Duration::from_secs(config.creation_timeout),
+				play: Duration::from_secs(config.play_timeout),
+			},
+			shutdown: None,
 
             // SERVER is Token(1), so start after that
-            // we can deal with a max of 126 connections
-            conns: Slab::new_starting_at(Token(2), 128)
+            conns: Slab::new_starting_at(Token(2), config.slab_size)
         }
     }
 
@@ -118,7 +220,7 @@ impl Server {
     ///
     /// This keeps the registration details neatly tucked away inside of our implementation.
     pub fn register(&mut self, event_loop: &mut EventLoop<Server>) -> io::Result<()> {
-		event_loop.timeout_ms(123, 1000).unwrap();
+		event_loop.timeout_ms(self.timer_token, self.tick_ms).unwrap();
         event_loop.register(
             &self.sock,
             self.token,
@@ -155,10 +257,10 @@ impl Server {
 
         // Log an error if there is no socket, but otherwise move on so we do not tear down the
         // entire server.
-        let sock = match self.sock.accept() {
+        let (sock, addr) = match self.sock.accept() {
             Ok(s) => {
                 match s {
-                    Some(sock) => sock.0,
+                    Some(sock) => sock,
                     None => {
                         println!("Failed to accept new socket");
                         self.reregister(event_loop);
@@ -172,9 +274,20 @@ impl Server {
                 return;
             }
         };
-		
-		let db = self.db.clone();
 
+		// rather than dropping an unwanted client silently, tell it why it is turned away
+		if let Some(reason) = self.admission(&addr) {
+			self.reject(sock, &addr, reason);
+			self.reregister(event_loop);
+			return;
+		}
+		if self.conns.count() >= self.slab_capacity {
+			let more = self.slab_size.min(self.max_connections - self.slab_capacity);
+			println!("Growing the connection slab from {} to {}", self.slab_capacity, self.slab_capacity + more);
+			self.conns.grow(more);
+			self.slab_capacity += more;
+		}
+		
         // `Slab#insert_with` is a wrapper around `Slab#insert`. I like `#insert_with` because I
         // make the `Token` required for creating a new connection.
         //
@@ -185,13 +298,15 @@ impl Server {
         // the event loop. Fancy...
         match self.conns.insert_with(|token| {
             println!("registering {:?} with event loop", token);
-            connection::Connection::new(sock, token, db)
+            connection::Connection::new(sock, token, addr)
         }) {
             Some(token) => {
                 // If we successfully insert, then register our connection.
+                // queue the greeting first so the registration already asks for writable events
+                self.find_connection_by_token(token).welcome();
                 match self.find_connection_by_token(token).register(event_loop) {
                     Ok(_) => {
-						self.find_connection_by_token(token).welcome();
+						self.game.stats_mut().connections = self.conns.count();
 					},
                     Err(e) => {
                         println!("Failed to register {:?} connection with event loop, {:?}", token, e);
@@ -209,45 +324,111 @@ impl Server {
         self.reregister(event_loop);
     }
 
+    /// Why a new client from addr can't be let in, None when it can.
+    ///
+    /// Every rejection is counted for the connections command.
+    fn admission(&mut self, addr: &SocketAddr) -> Option<&'static str> {
+        if self.shutdown.is_some() {
+            return Some(connection_messages::SHUTTING_DOWN);
+        }
+
+        let from_address = self.conns.iter().filter(|conn| conn.ip() == addr.ip()).count();
+        match limits::admit(self.conns.count(), from_address, self.max_connections, self.max_per_ip) {
+            Ok(()) => None,
+            Err(Rejection::Full) => {
+                self.game.stats_mut().rejected_full += 1;
+                Some(connection_messages::SERVER_FULL)
+            },
+            Err(Rejection::TooManyFromAddress) => {
+                self.game.stats_mut().rejected_per_ip += 1;
+                Some(connection_messages::TOO_MANY_FROM_ADDRESS)
+            },
+        }
+    }
+
+    /// Send the reason a client is turned away and close its socket.
+    ///
+    /// The socket is new and its send buffer empty, so one line always fits in a single write.
+    fn reject(&mut self, mut sock: TcpStream, addr: &SocketAddr, reason: &str) {
+        println!("Rejecting connection from {}: {}", addr, reason.trim());
+        if let Err(e) = sock.write(reason.as_bytes()) {
+            println!("Failed to tell {} it was rejected: {:?}", addr, e);
+        }
+    }
+
     /// Forward a readable event to an established connection.
     ///
     /// Connections are identified by the token provided to us from the event loop. Once a read has
-    /// finished, push the receive buffer into the all the existing connections so we can
-    /// broadcast.
+    /// finished, every complete line is handled by the connection as its own command.
     fn readable(&mut self, event_loop: &mut EventLoop<Server>, token: Token) -> io::Result<()> {
         info!("server conn readable; token={:?}", token);
-        let message = try!(self.find_connection_by_token(token).readable());
+        let lines = try!(self.find_connection_by_token(token).readable());
 
-        if message.remaining() == message.capacity() { // is_empty
+        if lines.is_empty() { // only a partial line or telnet commands arrived
             return Ok(());
         }
 
-        // TODO pipeine this whole thing
+        // accounts logged on elsewhere, a logon to one of them takes that session over
+        let sessions: Vec<String> = self.conns.iter()
+            .filter(|conn| conn.token != token && conn.is_logged_on())
+            .map(|conn| conn.username().to_string())
+            .collect();
+
+        // a failure is returned to `ready`, which resets the connection
+        {
+            let conn = &mut self.conns[token];
+            for line in lines {
+                try!(conn.handle_input(line, &mut self.bus, &mut self.game, &sessions, &mut self.rng));
+            }
+        }
+
+        if self.conns[token].took_over() {
+            self.take_over(event_loop, token);
+        }
+
+        self.deliver_messages(event_loop);
+        Ok(())
+    }
+
+    /// Fan out everything published on the bus to the other playing connections subscribed to
+    /// the message's target.
+    ///
+    /// Each message is also appended to the transaction log for history.
+    fn deliver_messages(&mut self, event_loop: &mut EventLoop<Server>) {
         let mut bad_tokens = Vec::new();
-		
-        // Queue up a write for all connected clients.
-        for conn in self.conns.iter_mut() {
-			if conn.token == token
-			{
-            // TODO: use references so we don't have to clone
-			let bytes = message.bytes();
-            let conn_send_buf = ByteBuf::from_slice(bytes);
-            conn.handle_input(&message)
-                .and_then(|_| conn.reregister(event_loop))
-                .unwrap_or_else(|e| {
-                    println!("Failed to queue message for {:?}: {:?}", conn.token, e);
-                    // We have a mutable borrow for the connection, so we cannot remove until the
-                    // loop is finished
-                    bad_tokens.push(conn.token)
-                });
-			}
+
+        for message in self.bus.drain() {
+            let mut delivered = 0;
+            for conn in self.conns.iter_mut() {
+                if conn.token != message.sender && conn.is_playing() && conn.is_subscribed(&message.target, &self.game) {
+                    delivered += 1;
+                    conn.send(message.text.clone());
+                    if conn.is_closed() {
+                        bad_tokens.push(conn.token);
+                        continue;
+                    }
+                    conn.reregister(event_loop).unwrap_or_else(|e| {
+                        println!("Failed to queue message for {:?}: {:?}", conn.token, e);
+                        // We have a mutable borrow for the connection, so we cannot remove until the
+                        // loop is finished
+                        bad_tokens.push(conn.token)
+                    });
+                }
+            }
+
+            // let the sender know a tell went nowhere
+            if let Target::User(_) = message.target {
+                if delivered == 0 && self.conns.contains(message.sender) {
+                    self.conns[message.sender].send(game::NO_SUCH_PLAYER.to_string());
+                }
+            }
+
+            self.log.append(Transaction::new(message.text, message.time_created, message.target.to_column()));
         }
 
         for t in bad_tokens {
             self.reset_connection(event_loop, t);
         }
-
-        Ok(())
     }
 
     fn reset_connection(&mut self, event_loop: &mut EventLoop<Server>, token: Token) {
@@ -255,8 +436,269 @@ impl Server {
             event_loop.shutdown();
         } else {
             println!("reset connection; token={:?}", token);
-            self.conns.remove(token);
+            if let Some(conn) = self.conns.remove(token) {
+                self.game.stats_mut().connections = self.conns.count();
+                // a player whose link dropped gets the chance to come back, one that was closed
+                // on purpose leaves right away
+                if conn.is_closing() {
+                    self.leave_world(conn);
+                } else {
+                    self.link_dead(conn);
+                }
+                self.deliver_messages(event_loop);
+            }
+        }
+    }
+
+    /// Save the character of a dropped connection and tell its room it is gone.
+    fn leave_world(&mut self, conn: connection::Connection) {
+        if !conn.in_world() {
+            return;
+        }
+
+        if let Some(character) = self.game.leave_world(conn.username()) {
+            let text = format!("{} leaves the game.\r\n", character.username);
+            self.bus.publish(Envelope::new(conn.token, Target::Room(character.room), text));
+        }
+    }
+
+    /// Keep the character of a dropped connection in the world and tell its room.
+    fn link_dead(&mut self, conn: connection::Connection) {
+        if !conn.in_world() {
+            return;
+        }
+
+        if let Some(room) = self.game.link_dead(conn.username()) {
+            let text = format!("{} has lost their link.\r\n", conn.username());
+            self.bus.publish(Envelope::new(conn.token, Target::Room(room), text));
+        }
+    }
+
+    /// Move the session of a player who logged in again from its old connection to the new one
+    /// at token, and disconnect the old one.
+    fn take_over(&mut self, event_loop: &mut EventLoop<Server>, token: Token) {
+        let username = self.conns[token].username().to_string();
+        let old = self.conns.iter()
+            // one that is closing still holds its character until it is reset
+            .find(|conn| conn.token != token && (conn.is_logged_on() || conn.in_world()) && conn.username() == username)
+            .map(|conn| conn.token);
+
+        if let Some(old) = old {
+            println!("{} moved from {:?} to {:?}", username, old, token);
+            let (output, channels) = self.conns[old].hand_over();
+            self.conns[token].take_over(output, channels);
+
+            let result = self.conns[old].reregister(event_loop);
+            if result.is_err() || self.conns[old].is_closed() {
+                self.reset_connection(event_loop, old);
+            }
+        }
+    }
+
+    /// Say goodbye to connections that sent nothing for longer than their state allows.
+    ///
+    /// They are closed like any other, so the message goes out before the socket is dropped.
+    fn drop_idle(&mut self, event_loop: &mut EventLoop<Server>) {
+        let now = Instant::now();
+        let idle: Vec<Token> = self.conns.iter()
+            .filter(|conn| !conn.is_closing() && conn.is_idle(&self.timeouts, now))
+            .map(|conn| conn.token)
+            .collect();
+
+        for token in idle {
+            println!("dropping idle connection; token={:?}", token);
+            let result = {
+                let conn = self.find_connection_by_token(token);
+                conn.send(connection_messages::IDLE_TIMEOUT.to_string());
+                conn.close();
+                conn.reregister(event_loop)
+            };
+            if result.is_err() || self.find_connection_by_token(token).is_closed() {
+                self.reset_connection(event_loop, token);
+            }
+        }
+    }
+
+    /// Start or restart the shutdown countdown on a signal or an admin's request, announce it,
+    /// and shut down once it runs out.
+    fn count_down(&mut self, event_loop: &mut EventLoop<Server>) {
+        let now = Instant::now();
+        let request = if shutdown::signalled() {
+            // a second signal means now
+            Some(Request::Shutdown(if self.shutdown.is_some() { 0 } else { shutdown::DEFAULT_COUNTDOWN }))
+        } else {
+            self.game.take_shutdown_request()
+        };
+        match request {
+            Some(Request::Shutdown(seconds)) => {
+                println!("shutting down in {} seconds", seconds);
+                self.shutdown = Some(shutdown::Countdown::new(seconds, now));
+            },
+            Some(Request::Copyover) => {
+                self.copyover(event_loop);
+                return;
+            },
+            None => {},
+        }
+
+        let announcement = match self.shutdown {
+            Some(ref mut countdown) if countdown.is_over(now) => {
+                self.shut_down(event_loop);
+                return;
+            },
+            Some(ref mut countdown) => countdown.announce(now),
+            None => None,
+        };
+        if let Some(text) = announcement {
+            self.bus.publish(Envelope::new(self.token, Target::All, text));
+            self.deliver_messages(event_loop);
+        }
+    }
+
+    /// Stop accepting clients, save every character, give the last output a little time to go out
+    /// and leave the event loop.
+    fn shut_down(&mut self, event_loop: &mut EventLoop<Server>) {
+        println!("shutting down");
+        event_loop.deregister(&self.sock).unwrap_or_else(|e| {
+            println!("Failed to deregister server {:?}, {:?}", self.token, e);
+        });
+
+        self.game.save_all();
+
+        for conn in self.conns.iter_mut() {
+            conn.send(connection_messages::SHUTDOWN_NOW.to_string());
+            conn.close();
+        }
+        self.flush_all();
+
+        self.log.close();
+        event_loop.shutdown();
+    }
+
+    /// Write out every connection's queued output until it is all sent or the flush deadline
+    /// passes. The event loop won't run again before the process ends or execs, so this writes
+    /// straight away instead of waiting for writable events.
+    fn flush_all(&mut self) {
+        let deadline = Instant::now() + Duration::from_millis(shutdown::FLUSH_DEADLINE_MS);
+        let mut failed = Vec::new();
+        loop {
+            let mut pending = false;
+            for conn in self.conns.iter_mut() {
+                if !conn.has_output() || failed.contains(&conn.token) {
+                    continue;
+                }
+                match conn.writable() {
+                    Ok(_) => pending = pending || conn.has_output(),
+                    Err(_) => failed.push(conn.token),
+                }
+            }
+            if !pending || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Hand every session over to the build on disk: save the characters, write the connections
+    /// to the state file and exec with their sockets left open. The new process calls `restore`.
+    ///
+    /// When the exec fails everyone is told and this process simply carries on.
+    #[cfg(unix)]
+    fn copyover(&mut self, event_loop: &mut EventLoop<Server>) {
+        println!("starting copyover");
+        self.game.save_all();
+        for conn in self.conns.iter_mut() {
+            conn.send(connection_messages::COPYOVER_START.to_string());
+        }
+        self.flush_all();
+
+        // closing connections are left behind, a taken over one no longer has a character
+        let state = copyover::State {
+            listener: self.sock.as_raw_fd(),
+            connections: self.conns.iter().filter(|conn| !conn.is_closing()).map(|conn| conn.save()).collect(),
+            link_dead: self.game.link_dead_players().into_iter()
+                .map(|(username, elapsed)| copyover::SavedLinkDead { username: username, elapsed: elapsed.as_secs() })
+                .collect(),
+        };
+        let prepared = copyover::inherit(state.listener)
+            .and_then(|_| state.connections.iter().fold(Ok(()), |result, conn| result.and_then(|_| copyover::inherit(conn.fd))))
+            .and_then(|_| state.save(copyover::STATE_PATH));
+
+        let error = match prepared {
+            Ok(_) => {
+                // the new process opens the log again, everything logged so far has to be written
+                self.log.close();
+                let error = copyover::exec(copyover::STATE_PATH);
+                self.log = transactionstorage::TransactionLog::new(&self.log_db);
+                error
+            },
+            Err(e) => e,
+        };
+
+        println!("Copyover failed, {:?}", error);
+        fs::remove_file(copyover::STATE_PATH).ok();
+        self.bus.publish(Envelope::new(self.token, Target::All, connection_messages::COPYOVER_FAILED.to_string()));
+        self.deliver_messages(event_loop);
+    }
+
+    #[cfg(not(unix))]
+    fn copyover(&mut self, event_loop: &mut EventLoop<Server>) {
+        println!("Copyover needs a unix system");
+        self.bus.publish(Envelope::new(self.token, Target::All, connection_messages::COPYOVER_FAILED.to_string()));
+        self.deliver_messages(event_loop);
+    }
+
+    /// Take the sessions a copyover handed over back into the slab and the event loop.
+    #[cfg(unix)]
+    pub fn restore(&mut self, state: copyover::State, event_loop: &mut EventLoop<Server>) {
+        if state.connections.len() > self.slab_capacity {
+            self.conns.grow(state.connections.len() - self.slab_capacity);
+            self.slab_capacity = state.connections.len();
+        }
+
+        let mut link_dead = state.link_dead;
+        for saved in state.connections {
+            let sock = unsafe { TcpStream::from_raw_fd(saved.fd) };
+            // the client may have gone while the server was restarting, a player gets to come back
+            // like after any other dropped connection
+            let addr = match sock.peer_addr() {
+                Ok(addr) => addr,
+                Err(e) => {
+                    println!("Dropping {} from the copyover, {:?}", saved.username, e);
+                    if saved.state == "play" {
+                        link_dead.push(copyover::SavedLinkDead { username: saved.username, elapsed: 0 });
+                    }
+                    continue;
+                }
+            };
+
+            let old = saved.token;
+            let game = &mut self.game;
+            let token = match self.conns.insert_with(|token| connection::Connection::restore(sock, token, addr, saved, game)) {
+                Some(token) => token,
+                None => {
+                    println!("Failed to insert restored connection into slab");
+                    continue;
+                }
+            };
+            println!("restored Token({}) as {:?}", old, token);
+            if let Err(e) = self.find_connection_by_token(token).register(event_loop) {
+                println!("Failed to register restored {:?}, {:?}", token, e);
+                self.reset_connection(event_loop, token);
+            }
+        }
+
+        // the copyover saved them, their grace period carries on where it was
+        for player in link_dead {
+            let restored = match character::load_character(player.username.clone()) {
+                Some(character) => self.game.restore_link_dead(character, Duration::from_secs(player.elapsed)),
+                None => Err("no saved character"),
+            };
+            if let Err(e) = restored {
+                println!("Failed to restore link-dead {}: {}", player.username, e.trim());
+            }
         }
+        self.game.stats_mut().connections = self.conns.count();
     }
 
     /// Find a connection in the slab using the given token.
@@ -0,0 +1,132 @@
+// Graceful shutdown
+// SIGINT, SIGTERM or the admin shutdown command start a countdown that players are told about.
+// When it runs out the server saves every character, sends what is left in the output queues
+// and stops. A second signal during the countdown skips the rest of it.
+
+extern crate libc;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// seconds of warning players get when no other time was asked for
+pub const DEFAULT_COUNTDOWN: u64 = 10;
+
+// how long the last messages get to reach the clients
+pub const FLUSH_DEADLINE_MS: u64 = 2000;
+
+// seconds left at which the countdown is announced again
+const ANNOUNCE_AT: [u64; 9] = [300, 120, 60, 30, 10, 5, 3, 2, 1];
+
+/// What an admin asked the server to do
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Request
+{
+	// stop after a countdown of this many seconds
+	Shutdown(u64),
+	// restart on the binary on disk, keeping everyone connected
+	Copyover,
+}
+
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int)
+{
+	SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+/// Catch SIGINT and SIGTERM instead of dying, the server looks for them every tick
+pub fn catch_signals()
+{
+	unsafe
+	{
+		let handler = on_signal as extern "C" fn(libc::c_int);
+		libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+		libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+	}
+}
+
+/// True once for every signal that came in since the last call
+pub fn signalled() -> bool
+{
+	SIGNALLED.swap(false, Ordering::SeqCst)
+}
+
+pub struct Countdown
+{
+	ends: Instant,
+	// seconds left at the last announcement
+	announced: Option<u64>,
+}
+
+impl Countdown
+{
+	pub fn new(seconds: u64, now: Instant) -> Countdown
+	{
+		Countdown
+		{
+			ends: now + Duration::from_secs(seconds),
+			announced: None,
+		}
+	}
+
+	fn seconds_left(&self, now: Instant) -> u64
+	{
+		if now >= self.ends
+		{
+			return 0;
+		}
+		let left = self.ends.duration_since(now);
+		left.as_secs() + if left.subsec_nanos() > 0 { 1 } else { 0 }
+	}
+
+	pub fn is_over(&self, now: Instant) -> bool
+	{
+		self.seconds_left(now) == 0
+	}
+
+	/// The announcement that is due, the first call always has one
+	pub fn announce(&mut self, now: Instant) -> Option<String>
+	{
+		let left = self.seconds_left(now);
+		if left == 0
+		{
+			return None;
+		}
+		let due = match self.announced
+		{
+			None => Some(left),
+			// only the nearest of the marks passed since the last one
+			Some(last) => ANNOUNCE_AT.iter().cloned().filter(|&mark| mark < last && left <= mark).min(),
+		};
+		due.map(|seconds|
+		{
+			self.announced = Some(seconds);
+			let unit = if seconds == 1 { "second" } else { "seconds" };
+			format!("{{YThe server shuts down in {} {}.{{x\r\n", seconds, unit)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::time::{Duration, Instant};
+
+	#[test]
+	fn announces_the_countdown_marks()
+	{
+		let start = Instant::now();
+		let at = |ms: u64| start + Duration::from_millis(ms);
+		let mut countdown = Countdown::new(12, start);
+
+		assert_eq!(countdown.announce(at(0)), Some("{YThe server shuts down in 12 seconds.{x\r\n".to_string()));
+		assert_eq!(countdown.announce(at(1000)), None);
+		// a slow tick skips straight to the nearest mark
+		assert_eq!(countdown.announce(at(8500)), Some("{YThe server shuts down in 5 seconds.{x\r\n".to_string()));
+		assert_eq!(countdown.announce(at(11000)), Some("{YThe server shuts down in 1 second.{x\r\n".to_string()));
+		assert!(!countdown.is_over(at(11999)));
+		assert!(countdown.is_over(at(12000)));
+		assert_eq!(countdown.announce(at(12000)), None);
+	}
+}
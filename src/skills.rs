@@ -0,0 +1,476 @@
+// Skills and spells
+// Every skill is a row of the SKILLS table. Characters keep how well they know each of theirs
+// and how long until they can use it again, cooldowns and mana/stamina regeneration run on the
+// server tick.
+
+extern crate rand;
+
+use self::rand::Rng;
+
+use character::Character;
+use combat;
+use combat::{Combatant, Stats};
+use commands::{Args, ArgSpec, Command, CommandOutput, CommandRegistry};
+use equipment;
+use gamehandler::CommandContext;
+use world::{capitalize, World};
+use Messages::game;
+
+// proficiency a new character starts its skills at
+pub const STARTING_PROFICIENCY: i32 = 25;
+
+// practice sessions a new character starts with
+pub const STARTING_PRACTICES: i32 = 5;
+
+// practicing stops helping here, the rest only comes from use
+pub const PRACTICE_MAX: i32 = 75;
+
+// ticks between two points of mana and stamina coming back
+const REGEN_TICKS: u64 = 5;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum SkillKind
+{
+	// physical, used by its own command and paid with stamina
+	Skill,
+	// used through cast and paid with mana
+	Spell,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum TargetType
+{
+	Myself,
+	Enemy,
+	// every enemy in the room
+	Room,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Effect
+{
+	// from, to and the attribute whose half is added
+	Damage(i32, i32, &'static str),
+	Heal(i32, i32, &'static str),
+}
+
+pub struct Skill
+{
+	pub name: &'static str,
+	pub kind: SkillKind,
+	pub cost: i32,
+	// ticks before it can be used again
+	pub cooldown: u32,
+	pub target: TargetType,
+	pub effect: Effect,
+	pub help: &'static str,
+}
+
+pub const SKILLS: [Skill; 6] = [
+	Skill { name: "kick", kind: SkillKind::Skill, cost: 2, cooldown: 2, target: TargetType::Enemy,
+		effect: Effect::Damage(1, 4, "str"), help: "Kick an enemy" },
+	Skill { name: "bash", kind: SkillKind::Skill, cost: 4, cooldown: 4, target: TargetType::Enemy,
+		effect: Effect::Damage(2, 6, "str"), help: "Slam into an enemy with your whole weight" },
+	Skill { name: "rally", kind: SkillKind::Skill, cost: 5, cooldown: 10, target: TargetType::Myself,
+		effect: Effect::Heal(3, 6, "str"), help: "Catch your breath and shake off some wounds" },
+	Skill { name: "missile", kind: SkillKind::Spell, cost: 3, cooldown: 2, target: TargetType::Enemy,
+		effect: Effect::Damage(2, 8, "int"), help: "A bolt of force at one enemy" },
+	Skill { name: "heal", kind: SkillKind::Spell, cost: 5, cooldown: 5, target: TargetType::Myself,
+		effect: Effect::Heal(4, 10, "int"), help: "Close your own wounds" },
+	Skill { name: "fireball", kind: SkillKind::Spell, cost: 8, cooldown: 6, target: TargetType::Room,
+		effect: Effect::Damage(3, 8, "int"), help: "Burn every enemy in the room" },
+];
+
+/// The skill a word refers to, a prefix is enough
+pub fn find_skill(word: &str) -> Option<&'static Skill>
+{
+	let word = word.to_lowercase();
+	if word.is_empty()
+	{
+		return None;
+	}
+	SKILLS.iter().find(|skill| skill.name == word).or_else(|| SKILLS.iter().find(|skill| skill.name.starts_with(&word[..])))
+}
+
+/// A skill a character knows
+#[derive(Clone,Debug,PartialEq)]
+pub struct Learned
+{
+	pub name: String,
+	// percent chance to pull it off
+	pub proficiency: i32,
+	// ticks until it can be used again, not saved
+	pub cooldown: u32,
+}
+
+impl Learned
+{
+	pub fn new(name: &str, proficiency: i32) -> Learned
+	{
+		Learned
+		{
+			name: name.to_string(),
+			proficiency: proficiency,
+			cooldown: 0,
+		}
+	}
+}
+
+/// What a new character knows, its type decides between fighting and magic and its race adds one
+pub fn starting_skills(race: &str, char_type: &str) -> Vec<Learned>
+{
+	let mut names = match char_type
+	{
+		// intelligent
+		"1" => vec!["missile", "heal"],
+		// athletic
+		"2" => vec!["kick", "bash"],
+		_ => vec!["kick", "heal"],
+	};
+
+	let racial = match race
+	{
+		"1" => "rally",
+		"2" => "missile",
+		"3" => "bash",
+		_ => "fireball",
+	};
+	if !names.contains(&racial)
+	{
+		names.push(racial);
+	}
+	names.into_iter().map(|name| Learned::new(name, STARTING_PROFICIENCY)).collect()
+}
+
+/// Give a newly created character its starting skills and practice sessions
+pub fn learn_starting_skills(character: &mut Character)
+{
+	let race = character.info.get("race").cloned().unwrap_or(String::new());
+	let char_type = character.info.get("type").cloned().unwrap_or(String::new());
+	character.skills = starting_skills(&race, &char_type);
+	character.practices = STARTING_PRACTICES;
+}
+
+/// Most mana and stamina a character can have, equipment included
+pub fn max_resource(character: &Character, world: &World, resource: &str) -> i32
+{
+	equipment::effective_attributes(character, world).get(resource).cloned().unwrap_or(0).max(0)
+}
+
+/// Count down cooldowns and let players get their breath back
+pub fn tick(world: &mut World, ticks: u64)
+{
+	let regen = ticks % REGEN_TICKS == 0;
+	let mut maxima = Vec::new();
+	if regen
+	{
+		for character in world.players()
+		{
+			maxima.push((character.username.clone(), max_resource(character, world, "mana"), max_resource(character, world, "stam")));
+		}
+	}
+
+	for character in world.players_mut()
+	{
+		for skill in character.skills.iter_mut()
+		{
+			if skill.cooldown > 0
+			{
+				skill.cooldown -= 1;
+			}
+		}
+	}
+
+	for (username, max_mana, max_stam) in maxima
+	{
+		if let Some(character) = world.player_mut(&username)
+		{
+			character.mana = (character.mana + 1).min(max_mana);
+			character.stam = (character.stam + 1).min(max_stam);
+		}
+	}
+}
+
+pub fn register_commands(commands: &mut CommandRegistry)
+{
+	commands.register(Command::new("cast", cast)
+		.arg(ArgSpec::word("spell"))
+		.arg(ArgSpec::word("target").optional())
+		.help("Cast a spell you know"));
+	commands.register(Command::new("practice", practice).alias("prac")
+		.arg(ArgSpec::word("skill").optional())
+		.help("List your skills or spend a practice session on one"));
+
+	for skill in SKILLS.iter().filter(|skill| skill.kind == SkillKind::Skill)
+	{
+		let mut command = Command::new(skill.name, perform).help(skill.help);
+		if skill.target == TargetType::Enemy
+		{
+			command = command.arg(ArgSpec::word("target").optional());
+		}
+		commands.register(command);
+	}
+}
+
+fn cast(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	match find_skill(args.word(0).unwrap_or(""))
+	{
+		Some(skill) if skill.kind == SkillKind::Spell => use_skill(context, skill, args.word(1)),
+		_ => CommandOutput::reply(game::UNKNOWN_SPELL.to_string()),
+	}
+}
+
+// every physical skill is a command of its own name
+fn perform(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	match find_skill(&args.command)
+	{
+		Some(skill) => use_skill(context, skill, args.word(0)),
+		None => CommandOutput::reply(game::UNKNOWN_SKILL.to_string()),
+	}
+}
+
+/// Who a skill is aimed at, an enemy skill without a target goes for whoever you are fighting
+fn targets(context: &CommandContext, skill: &Skill, word: Option<&str>) -> Result<Vec<Combatant>, &'static str>
+{
+	let character = context.character();
+	match skill.target
+	{
+		TargetType::Myself => Ok(vec![Combatant::Player(context.username.clone())]),
+		TargetType::Enemy => match word
+		{
+			Some(word) => combat::find_target(context.world, &context.username, &character.room, word).map(|target| vec![target]),
+			None => match character.fighting.clone()
+			{
+				Some(target) => Ok(vec![target]),
+				None => Err(game::NO_TARGET),
+			},
+		},
+		TargetType::Room =>
+		{
+			let mobs: Vec<Combatant> = context.world.population().in_room(&character.room).into_iter()
+				.filter(|id| !combat::is_peaceful(context.world, *id))
+				.map(Combatant::Mob)
+				.collect();
+			if mobs.is_empty() { Err(game::NO_TARGET) } else { Ok(mobs) }
+		},
+	}
+}
+
+fn use_skill(context: &mut CommandContext, skill: &Skill, word: Option<&str>) -> CommandOutput
+{
+	let (proficiency, cooldown) = match context.character().skills.iter().find(|learned| learned.name == skill.name)
+	{
+		Some(learned) => (learned.proficiency, learned.cooldown),
+		None => return CommandOutput::reply(game::UNKNOWN_SKILL.to_string()),
+	};
+	if cooldown > 0
+	{
+		return CommandOutput::reply(format!("You can't use {} again for {} more ticks.\r\n", skill.name, cooldown));
+	}
+
+	let available = match skill.kind
+	{
+		SkillKind::Skill => context.character().stam,
+		SkillKind::Spell => context.character().mana,
+	};
+	if available < skill.cost
+	{
+		return CommandOutput::reply(match skill.kind
+		{
+			SkillKind::Skill => game::TOO_TIRED.to_string(),
+			SkillKind::Spell => game::NO_MANA.to_string(),
+		});
+	}
+
+	let targets = match targets(context, skill, word)
+	{
+		Ok(targets) => targets,
+		Err(message) => return CommandOutput::reply(message.to_string()),
+	};
+
+	let success = context.rng.gen_range(0, 100) < proficiency;
+	// a failed attempt costs half
+	let cost = if success { skill.cost } else { skill.cost / 2 };
+	let improved = context.rng.gen_range(0, 100) < (100 - proficiency) / 3;
+	{
+		let character = context.character_mut();
+		match skill.kind
+		{
+			SkillKind::Skill => character.stam -= cost,
+			SkillKind::Spell => character.mana -= cost,
+		}
+		if let Some(learned) = character.skills.iter_mut().find(|learned| learned.name == skill.name)
+		{
+			learned.cooldown = skill.cooldown;
+			// practice makes perfect, the less you know the faster you learn
+			if improved
+			{
+				learned.proficiency = (learned.proficiency + 1).min(100);
+			}
+		}
+	}
+
+	let room = context.character().room.clone();
+	let me = Combatant::Player(context.username.clone());
+	let mut output = CommandOutput::new();
+	if !success
+	{
+		output.reply = match skill.kind
+		{
+			SkillKind::Skill => format!("You try to {} but fumble it.\r\n", skill.name),
+			SkillKind::Spell => game::LOST_CONCENTRATION.to_string(),
+		};
+	}
+	else
+	{
+		for target in targets
+		{
+			apply(context.world, skill, &me, &target, &room, context.rng, &mut output);
+		}
+	}
+
+	if improved
+	{
+		output.reply = output.reply.clone() + &format!("You have become better at {}!\r\n", skill.name);
+	}
+	output
+}
+
+/// The effect of a skill that worked on one target
+fn apply<R: Rng>(world: &mut World, skill: &Skill, user: &Combatant, target: &Combatant, room: &str, rng: &mut R, output: &mut CommandOutput)
+{
+	let user_name = match *user
+	{
+		Combatant::Player(ref name) => name.clone(),
+		_ => combat::name_of(world, user),
+	};
+	let attributes = match combat::character_of(world, user)
+	{
+		Some(character) => equipment::effective_attributes(character, world),
+		None => return,
+	};
+
+	match skill.effect
+	{
+		Effect::Damage(from, to, attribute) =>
+		{
+			let damage = rng.gen_range(from, to + 1) + attributes.get(attribute).cloned().unwrap_or(0) / 2;
+			let target_name = combat::name_of(world, target);
+			output.to_room(room, format!("{}'s {} hits {} for {} damage.\r\n", capitalize(&user_name), skill.name, target_name, damage));
+			if let Combatant::Player(ref username) = *user
+			{
+				combat::engage(world, username, target);
+			}
+			combat::hurt(world, user, target, damage, output);
+		},
+		Effect::Heal(from, to, attribute) =>
+		{
+			let amount = rng.gen_range(from, to + 1) + attributes.get(attribute).cloned().unwrap_or(0) / 2;
+			let max_hp = match combat::character_of(world, target)
+			{
+				Some(character) => Stats::of(character, world).max_hp,
+				None => return,
+			};
+			if let Some(character) = combat::character_of_mut(world, target)
+			{
+				character.hp = (character.hp + amount).min(max_hp);
+			}
+			output.to_room(room, format!("{} uses {} and looks better.\r\n", capitalize(&user_name), skill.name));
+		},
+	}
+}
+
+fn practice(context: &mut CommandContext, args: &Args) -> CommandOutput
+{
+	let word = match args.word(0)
+	{
+		Some(word) => word,
+		None =>
+		{
+			let character = context.character();
+			let mut text = String::from("You know:\r\n");
+			for learned in character.skills.iter()
+			{
+				let kind = match find_skill(&learned.name).map(|skill| skill.kind)
+				{
+					Some(SkillKind::Spell) => "spell",
+					_ => "skill",
+				};
+				text = text + &format!("  {:<10} {:<6} {:>3}%\r\n", learned.name, kind, learned.proficiency);
+			}
+			text = text + &format!("You have {} practice sessions left.\r\n", character.practices);
+			return CommandOutput::reply(text);
+		},
+	};
+
+	let name = match find_skill(word)
+	{
+		Some(skill) => skill.name,
+		None => return CommandOutput::reply(game::UNKNOWN_SKILL.to_string()),
+	};
+	if context.character().practices <= 0
+	{
+		return CommandOutput::reply(game::NO_PRACTICES.to_string());
+	}
+
+	let gain = 5 + context.character().base_attr("int") / 2;
+	let character = context.character_mut();
+	let learned = match character.skills.iter_mut().find(|learned| learned.name == name)
+	{
+		Some(learned) => learned,
+		None => return CommandOutput::reply(game::UNKNOWN_SKILL.to_string()),
+	};
+	if learned.proficiency >= PRACTICE_MAX
+	{
+		return CommandOutput::reply(format!("You can only get better at {} by using it now.\r\n", name));
+	}
+
+	learned.proficiency = (learned.proficiency + gain).min(PRACTICE_MAX);
+	let proficiency = learned.proficiency;
+	character.practices -= 1;
+	CommandOutput::reply(format!("You practice {}, you know it {}% now.\r\n", name, proficiency))
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use commands::Resolution;
+
+	#[test]
+	fn finds_skills_by_prefix()
+	{
+		assert_eq!(find_skill("fire").map(|skill| skill.name), Some("fireball"));
+		assert_eq!(find_skill("HEAL").map(|skill| skill.name), Some("heal"));
+		assert!(find_skill("").is_none());
+		assert!(find_skill("juggle").is_none());
+	}
+
+	#[test]
+	fn race_and_type_decide_starting_skills()
+	{
+		let names = |race, char_type| starting_skills(race, char_type).into_iter().map(|learned| learned.name).collect::<Vec<_>>();
+		assert_eq!(names("1", "2"), vec!["kick", "bash", "rally"]);
+		assert_eq!(names("2", "1"), vec!["missile", "heal"]);
+		assert_eq!(names("4", "3"), vec!["kick", "heal", "fireball"]);
+		assert!(starting_skills("3", "1").iter().all(|learned| learned.proficiency == STARTING_PROFICIENCY));
+	}
+
+	#[test]
+	fn skill_commands_leave_kill_on_k()
+	{
+		let mut registry = CommandRegistry::new();
+		combat::register_commands(&mut registry);
+		register_commands(&mut registry);
+		let resolved = |verb| match registry.resolve(verb, false)
+		{
+			Resolution::Found(command) => Some(command.name),
+			_ => None,
+		};
+		assert_eq!(resolved("k"), Some("kill"));
+		assert_eq!(resolved("kic"), Some("kick"));
+		assert_eq!(resolved("ba"), Some("bash"));
+	}
+}
@@ -0,0 +1,135 @@
+extern crate rusqlite;
+
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use storage::{DataColumn, Storage};
+
+fn to_io_error(e: rusqlite::Error) -> Error
+{
+	Error::new(ErrorKind::Other, format!("sqlite: {}", e))
+}
+
+/// Keeps every table in one SQLite database, one row per column of an entry.
+///
+/// Pointing it at db/messages.db keeps players and characters next to the message log.
+pub struct SqliteStorage
+{
+	dbconn: rusqlite::Connection,
+}
+
+impl SqliteStorage
+{
+	pub fn new(filepath: &str) -> Result<SqliteStorage, Error>
+	{
+		let connection = try!(rusqlite::Connection::open(Path::new(filepath)).map_err(to_io_error));
+		let storage = SqliteStorage
+		{
+			dbconn: connection,
+		};
+		try!(storage.create_tables());
+		Ok(storage)
+	}
+
+	fn create_tables(&self) -> Result<(), Error>
+	{
+		self.dbconn.execute_batch("CREATE TABLE IF NOT EXISTS records (
+		  tbl             TEXT NOT NULL,
+		  key             TEXT NOT NULL,
+		  position        INTEGER NOT NULL,
+		  col             TEXT NOT NULL,
+		  value           TEXT NOT NULL,
+		  PRIMARY KEY (tbl, key, position)
+		  );").map_err(to_io_error)
+	}
+
+	fn write_entry(&self, table: &str, key: &str, data: &Vec<DataColumn>) -> Result<(), rusqlite::Error>
+	{
+		try!(self.dbconn.execute("DELETE FROM records WHERE tbl = $1 AND key = $2", &[&table, &key]));
+
+		let mut position: i64 = 0;
+		for col in data
+		{
+			try!(self.dbconn.execute("INSERT INTO records (tbl, key, position, col, value)
+			VALUES ($1, $2, $3, $4, $5)", &[&table, &key, &position, &col.get_column(), &col.get_data()]));
+			position += 1;
+		}
+		Ok(())
+	}
+}
+
+impl Storage for SqliteStorage
+{
+	fn get(&self, table: &str, key: &str) -> Result<Option<Vec<DataColumn>>, Error>
+	{
+		let mut stmt = try!(self.dbconn.prepare("SELECT col, value FROM records
+		WHERE tbl = $1 AND key = $2 ORDER BY position").map_err(to_io_error));
+		let rows = try!(stmt.query_map(&[&table, &key], |row|
+		{
+			DataColumn::new(row.get(0), row.get(1))
+		}).map_err(to_io_error));
+
+		let mut data = Vec::new();
+		for row in rows
+		{
+			data.push(try!(row.map_err(to_io_error)));
+		}
+
+		if data.is_empty()
+		{
+			return Ok(None);
+		}
+		Ok(Some(data))
+	}
+
+	fn put(&self, table: &str, key: &str, data: &Vec<DataColumn>) -> Result<(), Error>
+	{
+		//the whole entry is replaced in one transaction
+		try!(self.dbconn.execute_batch("BEGIN").map_err(to_io_error));
+		match self.write_entry(table, key, data)
+		{
+			Ok(_) => self.dbconn.execute_batch("COMMIT").map_err(to_io_error),
+			Err(e) =>
+			{
+				let _ = self.dbconn.execute_batch("ROLLBACK");
+				Err(to_io_error(e))
+			}
+		}
+	}
+
+	fn delete(&self, table: &str, key: &str) -> Result<bool, Error>
+	{
+		let deleted = try!(self.dbconn.execute("DELETE FROM records WHERE tbl = $1 AND key = $2",
+			&[&table, &key]).map_err(to_io_error));
+		Ok(deleted > 0)
+	}
+
+	fn exists(&self, table: &str, key: &str) -> bool
+	{
+		let result = self.dbconn.query_row("SELECT COUNT(*) FROM records WHERE tbl = $1 AND key = $2",
+			&[&table, &key], |row| { let count: i64 = row.get(0); count });
+		match result
+		{
+			Ok(count) => count > 0,
+			Err(e) => { println!("Failed to check entry {}/{}: {}", table, key, e); false },
+		}
+	}
+
+	fn list(&self, table: &str) -> Result<Vec<String>, Error>
+	{
+		let mut stmt = try!(self.dbconn.prepare("SELECT DISTINCT key FROM records
+		WHERE tbl = $1 ORDER BY key").map_err(to_io_error));
+		let rows = try!(stmt.query_map(&[&table], |row|
+		{
+			let key: String = row.get(0);
+			key
+		}).map_err(to_io_error));
+
+		let mut keys = Vec::new();
+		for row in rows
+		{
+			keys.push(try!(row.map_err(to_io_error)));
+		}
+		Ok(keys)
+	}
+}
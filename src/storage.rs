@@ -1,19 +1,44 @@
 use std::io::Read;
 use std::io::Write;
-use std::any::Any;
 use std::fs;
 use std::fs::File;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::str;
 
+use sqlitestorage::SqliteStorage;
 
-pub fn get_db() -> DB
+pub const DEFAULT_PATH: &'static str = "./db/";
+
+// The server runs on one thread, so every caller shares the storage set up here
+thread_local!(static DB: RefCell<Rc<dyn Storage>> = RefCell::new(Rc::new(FlatFileStorage::new(DEFAULT_PATH.to_string()))));
+
+pub fn get_db() -> Rc<dyn Storage>
+{
+	DB.with(|db| db.borrow().clone())
+}
+
+//replace the storage used by get_db, e.g. with a MemoryStorage in tests
+pub fn set_db(storage: Rc<dyn Storage>)
 {
-	DB
+	DB.with(|db| *db.borrow_mut() = storage);
+}
+
+//open a backend by name, "file" takes a directory and "sqlite" a database file
+pub fn open_storage(backend: &str, path: &str) -> Result<Rc<dyn Storage>, Error>
+{
+	match backend
 	{
-		connection_string: "./db/".to_string(),
+		"file" => Ok(Rc::new(FlatFileStorage::new(path.to_string()))),
+		"sqlite" => Ok(Rc::new(try!(SqliteStorage::new(path)))),
+		"memory" => Ok(Rc::new(MemoryStorage::new())),
+		_ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown storage backend {}", backend))),
 	}
 }
 
+#[derive(Clone,Debug)]
 pub struct DataColumn
 {
 	column: String,
@@ -30,91 +55,367 @@ impl DataColumn
 			data: value
 		}
 	}
-	
-	pub fn insert_string(&mut self) -> String
+
+	pub fn get_column(&self) -> &str
+	{
+		&self.column
+	}
+
+	pub fn get_data(&self) -> &str
+	{
+		&self.data
+	}
+}
+
+//find the value stored for a column
+pub fn get_value(data: &Vec<DataColumn>, column: &str) -> Option<String>
+{
+	for col in data
+	{
+		if col.column == column
+		{
+			return Some(col.data.clone());
+		}
+	}
+	None
+}
+
+/// A key/value store of tables, every entry is a list of columns
+pub trait Storage
+{
+	fn get(&self, table: &str, key: &str) -> Result<Option<Vec<DataColumn>>, Error>;
+	fn put(&self, table: &str, key: &str, data: &Vec<DataColumn>) -> Result<(), Error>;
+	// true when there was an entry to delete
+	fn delete(&self, table: &str, key: &str) -> Result<bool, Error>;
+	fn exists(&self, table: &str, key: &str) -> bool;
+	fn list(&self, table: &str) -> Result<Vec<String>, Error>;
+}
+
+/// Something that is saved as one entry of a table
+pub trait Record: Sized
+{
+	fn table() -> &'static str;
+	fn key(&self) -> String;
+	fn to_columns(&self) -> Vec<DataColumn>;
+	fn from_columns(key: &str, data: Vec<DataColumn>) -> Result<Self, Error>;
+}
+
+pub fn load<R: Record>(db: &dyn Storage, key: &str) -> Result<Option<R>, Error>
+{
+	match try!(db.get(R::table(), key))
 	{
-		return format!("{}:{}", self.column, self.data)
-	}		
+		Some(data) => Ok(Some(try!(R::from_columns(key, data)))),
+		None => Ok(None),
+	}
+}
+
+pub fn save<R: Record>(db: &dyn Storage, record: &R) -> Result<(), Error>
+{
+	db.put(R::table(), &record.key(), &record.to_columns())
 }
 
-pub struct DB
+fn escape(value: &str) -> String
+{
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars()
+	{
+		match c
+		{
+			'\\' => escaped.push_str("\\\\"),
+			'\n' => escaped.push_str("\\n"),
+			'\r' => escaped.push_str("\\r"),
+			':' => escaped.push_str("\\c"),
+			_ => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+fn unescape(value: &str) -> String
+{
+	let mut unescaped = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(c) = chars.next()
+	{
+		if c != '\\'
+		{
+			unescaped.push(c);
+			continue;
+		}
+
+		match chars.next()
+		{
+			Some('n') => unescaped.push('\n'),
+			Some('r') => unescaped.push('\r'),
+			Some('c') => unescaped.push(':'),
+			Some(other) => unescaped.push(other),
+			None => unescaped.push('\\'),
+		}
+	}
+	unescaped
+}
+
+//keys become file names, so anything that could leave the table directory is hex encoded
+fn encode_key(key: &str) -> String
+{
+	let mut encoded = String::new();
+	for byte in key.bytes()
+	{
+		let c = byte as char;
+		if c.is_ascii_alphanumeric() || c == '_' || c == '-'
+		{
+			encoded.push(c);
+		}
+		else
+		{
+			encoded.push_str(&format!("%{:02X}", byte));
+		}
+	}
+	encoded
+}
+
+fn decode_key(encoded: &str) -> String
+{
+	let bytes = encoded.as_bytes();
+	let mut decoded = Vec::new();
+	let mut index = 0;
+	while index < bytes.len()
+	{
+		if bytes[index] == b'%' && index + 2 < bytes.len()
+		{
+			let hex = str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or("");
+			if let Ok(byte) = u8::from_str_radix(hex, 16)
+			{
+				decoded.push(byte);
+				index += 3;
+				continue;
+			}
+		}
+		decoded.push(bytes[index]);
+		index += 1;
+	}
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Entries are files at <root>/<table>/<key>.db holding one column:value per line
+pub struct FlatFileStorage
 {
 	connection_string: String,
 }
 
-impl DB
+impl FlatFileStorage
 {
-	pub fn new(connstring: String) -> DB
+	pub fn new(connstring: String) -> FlatFileStorage
 	{
-		DB
+		FlatFileStorage
 		{
 			connection_string: connstring,
 		}
 	}
-	
-	pub fn insert(self, table: &str, key:&str, data: Vec<DataColumn>) -> Result<(), Error>
+
+	fn table_dir(&self, table: &str) -> String
 	{
-		let dir = self.connection_string.clone() + "//" + table;
-		match fs::create_dir_all(dir)
+		format!("{}//{}", self.connection_string, table)
+	}
+
+	fn entry_path(&self, table: &str, key: &str) -> String
+	{
+		format!("{}//{}.db", self.table_dir(table), encode_key(key))
+	}
+}
+
+impl Storage for FlatFileStorage
+{
+	fn get(&self, table: &str, key: &str) -> Result<Option<Vec<DataColumn>>, Error>
+	{
+		let mut file = match File::open(self.entry_path(table, key))
+		{
+			Ok(f) => f,
+			Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(e),
+		};
+
+		let mut content = String::new();
+		try!(file.read_to_string(&mut content));
+
+		let mut data: Vec<DataColumn> = Vec::new();
+		for line in content.lines()
 		{
-			Ok(ok) => ok,
-			Err(_) => {},
+			if line.is_empty()
+			{
+				continue;
+			}
+
+			//values may contain colons, only the first one separates the column
+			let mut parts = line.splitn(2, ':');
+			let column = unescape(parts.next().unwrap_or(""));
+			let value = unescape(parts.next().unwrap_or(""));
+			data.push(DataColumn::new(column, value));
 		}
-		
-		let filepath = self.connection_string + "//" + table + "//" + key + ".db";
-		
-		let mut file = try!(File::create(filepath));
-		
-		
+		Ok(Some(data))
+	}
+
+	fn put(&self, table: &str, key: &str, data: &Vec<DataColumn>) -> Result<(), Error>
+	{
+		try!(fs::create_dir_all(self.table_dir(table)));
+
 		let mut content = String::new();
-		
 		for col in data
 		{
-			let mut col_str = col;
-			content = content + "\n" + &col_str.insert_string();
+			content = content + "\n" + &escape(&col.column) + ":" + &escape(&col.data);
 		}
-		file.write_all(content.as_bytes());
-		Ok(())
+
+		//write a temporary file and rename it over the entry so a crash never leaves half a record
+		let filepath = self.entry_path(table, key);
+		let temppath = filepath.clone() + ".tmp";
+		{
+			let mut file = try!(File::create(&temppath));
+			try!(file.write_all(content.as_bytes()));
+			try!(file.sync_all());
+		}
+		fs::rename(temppath, filepath)
 	}
-	
-	pub fn entry_exists(&mut self, table: &str, key: &str) -> bool
+
+	fn delete(&self, table: &str, key: &str) -> Result<bool, Error>
 	{
-		let filepath = format!("{}//{}//{}.db", self.connection_string, table, key);
-		let file = File::open(filepath);
-		match file
+		match fs::remove_file(self.entry_path(table, key))
 		{
-			Ok(_) =>{println!("file does exist"); return true;},
-			Err(_)=>{println!("file does not exist"); return false;},
+			Ok(_) => Ok(true),
+			Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(false),
+			Err(e) => Err(e),
 		}
 	}
-	
-	pub fn read_entry(&self, table: &str, key: &str) -> Result<Vec<DataColumn>, Error>
+
+	fn exists(&self, table: &str, key: &str) -> bool
 	{
-		let filepath = format!("{}//{}//{}.db", self.connection_string, table, key);
-		let file = File::open(filepath);
-		let mut content = String::new();
-		let mut data: Vec<DataColumn> = Vec::new();
-		
-		match file
+		fs::metadata(self.entry_path(table, key)).is_ok()
+	}
+
+	fn list(&self, table: &str) -> Result<Vec<String>, Error>
+	{
+		let entries = match fs::read_dir(self.table_dir(table))
 		{
-			Ok(f) => 
+			Ok(entries) => entries,
+			Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e) => return Err(e),
+		};
+
+		let mut keys = Vec::new();
+		for entry in entries
+		{
+			let name = try!(entry).file_name().to_string_lossy().into_owned();
+			if name.ends_with(".db")
 			{
-				let mut file_ps = f;
-				try!(file_ps.read_to_string(&mut content));
-				let lines = content.lines();
-				
-				for line in lines
-				{
-					let parts: Vec<&str> = line.split(':').collect();
-					data.push(DataColumn::new
-						(parts[0].to_string(), 
-						if parts.len() > 1 {parts[1].to_string()}
-						else {String::new()}
-						));
-				}
-			},
-			Err(_) => {},
+				keys.push(decode_key(&name[..name.len() - 3]));
+			}
+		}
+		keys.sort();
+		Ok(keys)
+	}
+}
+
+/// Keeps every entry in memory, nothing survives a restart
+pub struct MemoryStorage
+{
+	tables: RefCell<HashMap<String, HashMap<String, Vec<DataColumn>>>>,
+}
+
+impl MemoryStorage
+{
+	pub fn new() -> MemoryStorage
+	{
+		MemoryStorage
+		{
+			tables: RefCell::new(HashMap::new()),
 		}
-		Ok(data)
 	}
-}
\ No newline at end of file
+}
+
+impl Storage for MemoryStorage
+{
+	fn get(&self, table: &str, key: &str) -> Result<Option<Vec<DataColumn>>, Error>
+	{
+		let tables = self.tables.borrow();
+		Ok(tables.get(table).and_then(|entries| entries.get(key)).cloned())
+	}
+
+	fn put(&self, table: &str, key: &str, data: &Vec<DataColumn>) -> Result<(), Error>
+	{
+		let mut tables = self.tables.borrow_mut();
+		tables.entry(table.to_string()).or_insert(HashMap::new()).insert(key.to_string(), data.clone());
+		Ok(())
+	}
+
+	fn delete(&self, table: &str, key: &str) -> Result<bool, Error>
+	{
+		let mut tables = self.tables.borrow_mut();
+		Ok(tables.get_mut(table).and_then(|entries| entries.remove(key)).is_some())
+	}
+
+	fn exists(&self, table: &str, key: &str) -> bool
+	{
+		let tables = self.tables.borrow();
+		tables.get(table).map(|entries| entries.contains_key(key)).unwrap_or(false)
+	}
+
+	fn list(&self, table: &str) -> Result<Vec<String>, Error>
+	{
+		let tables = self.tables.borrow();
+		let mut keys: Vec<String> = match tables.get(table)
+		{
+			Some(entries) => entries.keys().cloned().collect(),
+			None => Vec::new(),
+		};
+		keys.sort();
+		Ok(keys)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use sqlitestorage::SqliteStorage;
+	use std::env;
+	use std::process;
+
+	fn round_trip(db: &dyn Storage)
+	{
+		let data = vec![
+			DataColumn::new("password".to_string(), "$rpbkdf2$0$AAAnEA==$salt:with:colons$".to_string()),
+			DataColumn::new("description".to_string(), "two\nlines \\ and a backslash".to_string()),
+		];
+
+		db.put("player", "../swu", &data).unwrap();
+		assert!(db.exists("player", "../swu"));
+
+		let loaded = db.get("player", "../swu").unwrap().unwrap();
+		assert_eq!(get_value(&loaded, "password"), get_value(&data, "password"));
+		assert_eq!(get_value(&loaded, "description"), get_value(&data, "description"));
+		assert_eq!(db.list("player").unwrap(), vec!["../swu".to_string()]);
+
+		assert!(db.delete("player", "../swu").unwrap());
+		assert!(db.get("player", "../swu").unwrap().is_none());
+	}
+
+	#[test]
+	fn flat_file_round_trip()
+	{
+		let dir = env::temp_dir().join(format!("mioserver-storage-{}", process::id()));
+		let db = FlatFileStorage::new(dir.to_string_lossy().into_owned());
+		round_trip(&db);
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn memory_round_trip()
+	{
+		round_trip(&MemoryStorage::new());
+	}
+
+	#[test]
+	fn sqlite_round_trip()
+	{
+		round_trip(&SqliteStorage::new(":memory:").unwrap());
+	}
+}
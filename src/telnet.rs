@@ -0,0 +1,544 @@
+// Telnet protocol layer
+// Strips IAC sequences out of the raw socket stream and answers option negotiation
+// https://tools.ietf.org/html/rfc854
+// https://tools.ietf.org/html/rfc1143
+
+pub const SE: u8 = 240;
+pub const SB: u8 = 250;
+pub const WILL: u8 = 251;
+pub const WONT: u8 = 252;
+pub const DO: u8 = 253;
+pub const DONT: u8 = 254;
+pub const IAC: u8 = 255;
+
+pub const OPT_ECHO: u8 = 1;
+pub const OPT_SGA: u8 = 3;
+pub const OPT_TTYPE: u8 = 24;
+
+// TTYPE subnegotiation, the client answers SEND with IS and its terminal type
+// https://tools.ietf.org/html/rfc1091
+pub const TTYPE_IS: u8 = 0;
+pub const TTYPE_SEND: u8 = 1;
+
+// Options the server agrees to perform when the client asks. ECHO is not one of them, the server
+// only offers it itself to hide a password and never echoes ordinary input.
+const SUPPORTED_LOCAL: [u8; 1] = [OPT_SGA];
+
+// Options the server is willing to let the client perform
+const SUPPORTED_REMOTE: [u8; 1] = [OPT_TTYPE];
+
+// longest subnegotiation payload kept, a terminal type is far shorter
+const MAX_SUBNEGOTIATION: usize = 256;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+enum ParseState
+{
+	Data,
+	Iac,
+	Negotiate(u8),
+	Subnegotiation,
+	SubnegotiationIac,
+}
+
+/// Options that are currently enabled on either side of the connection
+#[derive(Clone,Debug)]
+pub struct TelnetOptions
+{
+	// options the server performs, e.g. ECHO
+	pub local: Vec<u8>,
+	// options the client performs
+	pub remote: Vec<u8>,
+}
+
+impl TelnetOptions
+{
+	pub fn new() -> TelnetOptions
+	{
+		TelnetOptions
+		{
+			local: Vec::new(),
+			remote: Vec::new(),
+		}
+	}
+
+	pub fn is_local(&self, option: u8) -> bool
+	{
+		self.local.contains(&option)
+	}
+
+	pub fn is_remote(&self, option: u8) -> bool
+	{
+		self.remote.contains(&option)
+	}
+}
+
+/// Result of decoding one chunk of socket input
+pub struct Decoded
+{
+	// plain data with every telnet command removed
+	pub data: Vec<u8>,
+	// negotiation replies that must be written back to the client
+	pub reply: Vec<u8>,
+	// completed subnegotiations as (option, payload)
+	pub subnegotiations: Vec<(u8, Vec<u8>)>,
+}
+
+pub struct TelnetCodec
+{
+	state: ParseState,
+	subnegotiation: Vec<u8>,
+	// the subnegotiation being read grew too long and is dropped at its SE
+	discarding: bool,
+	options: TelnetOptions,
+	// options we asked for and are waiting on an answer
+	pending_local: Vec<u8>,
+	pending_remote: Vec<u8>,
+	// pending options we no longer want, the answer is turned down when it arrives (RFC 1143 queue)
+	cancelled_local: Vec<u8>,
+	cancelled_remote: Vec<u8>,
+}
+
+fn command(cmd: u8, option: u8) -> Vec<u8>
+{
+	vec![IAC, cmd, option]
+}
+
+fn remove_option(list: &mut Vec<u8>, option: u8) -> bool
+{
+	match list.iter().position(|o| *o == option)
+	{
+		Some(index) => { list.remove(index); true },
+		None => false,
+	}
+}
+
+fn add_option(list: &mut Vec<u8>, option: u8)
+{
+	if !list.contains(&option)
+	{
+		list.push(option);
+	}
+}
+
+// what to send once the client agreed to perform option
+fn remote_enabled(option: u8) -> Vec<u8>
+{
+	match option
+	{
+		OPT_TTYPE => vec![IAC, SB, OPT_TTYPE, TTYPE_SEND, IAC, SE],
+		_ => Vec::new(),
+	}
+}
+
+/// The terminal type in a TTYPE subnegotiation payload, None for anything but IS
+pub fn terminal_type(payload: &[u8]) -> Option<String>
+{
+	match payload.split_first()
+	{
+		Some((&TTYPE_IS, name)) => Some(String::from_utf8_lossy(name).into_owned()),
+		_ => None,
+	}
+}
+
+/// Double every IAC byte so outgoing text is not read as a command
+pub fn escape(data: &[u8]) -> Vec<u8>
+{
+	let mut escaped = Vec::with_capacity(data.len());
+	for byte in data
+	{
+		escaped.push(*byte);
+		if *byte == IAC
+		{
+			escaped.push(IAC);
+		}
+	}
+	escaped
+}
+
+impl TelnetCodec
+{
+	pub fn new() -> TelnetCodec
+	{
+		TelnetCodec
+		{
+			state: ParseState::Data,
+			subnegotiation: Vec::new(),
+			discarding: false,
+			options: TelnetOptions::new(),
+			pending_local: Vec::new(),
+			pending_remote: Vec::new(),
+			cancelled_local: Vec::new(),
+			cancelled_remote: Vec::new(),
+		}
+	}
+
+	/// A codec that carries on with options negotiated earlier, e.g. before a copyover
+	pub fn with_options(options: TelnetOptions) -> TelnetCodec
+	{
+		let mut codec = TelnetCodec::new();
+		codec.options = options;
+		codec
+	}
+
+	pub fn options(&self) -> &TelnetOptions
+	{
+		&self.options
+	}
+
+	/// Decode raw socket bytes, partial sequences are kept until the next call
+	pub fn decode(&mut self, input: &[u8]) -> Decoded
+	{
+		let mut decoded = Decoded
+		{
+			data: Vec::new(),
+			reply: Vec::new(),
+			subnegotiations: Vec::new(),
+		};
+
+		for byte in input
+		{
+			let byte = *byte;
+			self.state = match self.state
+			{
+				ParseState::Data =>
+				{
+					match byte
+					{
+						IAC => ParseState::Iac,
+						// NVT sends CR NUL for a bare carriage return
+						0 => ParseState::Data,
+						_ => { decoded.data.push(byte); ParseState::Data },
+					}
+				},
+				ParseState::Iac =>
+				{
+					match byte
+					{
+						IAC => { decoded.data.push(IAC); ParseState::Data },
+						WILL | WONT | DO | DONT => ParseState::Negotiate(byte),
+						SB =>
+						{
+							self.subnegotiation.clear();
+							self.discarding = false;
+							ParseState::Subnegotiation
+						},
+						// NOP, GA, AYT and friends carry no data for us
+						_ => ParseState::Data,
+					}
+				},
+				ParseState::Negotiate(cmd) =>
+				{
+					let reply = self.negotiate(cmd, byte);
+					decoded.reply.extend(reply);
+					ParseState::Data
+				},
+				ParseState::Subnegotiation =>
+				{
+					match byte
+					{
+						IAC => ParseState::SubnegotiationIac,
+						_ => { self.keep_subnegotiation(byte); ParseState::Subnegotiation },
+					}
+				},
+				ParseState::SubnegotiationIac =>
+				{
+					match byte
+					{
+						IAC => { self.keep_subnegotiation(IAC); ParseState::Subnegotiation },
+						SE =>
+						{
+							if !self.discarding && !self.subnegotiation.is_empty()
+							{
+								let option = self.subnegotiation[0];
+								let payload = self.subnegotiation[1..].to_vec();
+								decoded.subnegotiations.push((option, payload));
+							}
+							self.subnegotiation.clear();
+							self.discarding = false;
+							ParseState::Data
+						},
+						_ => ParseState::Subnegotiation,
+					}
+				},
+			};
+		}
+
+		decoded
+	}
+
+	fn keep_subnegotiation(&mut self, byte: u8)
+	{
+		if self.discarding
+		{
+			return;
+		}
+		if self.subnegotiation.len() >= MAX_SUBNEGOTIATION
+		{
+			self.subnegotiation.clear();
+			self.discarding = true;
+			return;
+		}
+		self.subnegotiation.push(byte);
+	}
+
+	/// Answer a WILL/WONT/DO/DONT from the client, only replying when our state changes
+	fn negotiate(&mut self, cmd: u8, option: u8) -> Vec<u8>
+	{
+		match cmd
+		{
+			WILL =>
+			{
+				if remove_option(&mut self.cancelled_remote, option)
+				{
+					// agreed to what we took back in the meantime
+					remove_option(&mut self.pending_remote, option);
+					command(DONT, option)
+				}
+				else if remove_option(&mut self.pending_remote, option)
+				{
+					add_option(&mut self.options.remote, option);
+					remote_enabled(option)
+				}
+				else if self.options.is_remote(option)
+				{
+					Vec::new()
+				}
+				else if SUPPORTED_REMOTE.contains(&option)
+				{
+					add_option(&mut self.options.remote, option);
+					let mut reply = command(DO, option);
+					reply.extend(remote_enabled(option));
+					reply
+				}
+				else
+				{
+					command(DONT, option)
+				}
+			},
+			WONT =>
+			{
+				remove_option(&mut self.cancelled_remote, option);
+				if remove_option(&mut self.pending_remote, option)
+				{
+					Vec::new()
+				}
+				else if remove_option(&mut self.options.remote, option)
+				{
+					command(DONT, option)
+				}
+				else
+				{
+					Vec::new()
+				}
+			},
+			DO =>
+			{
+				if remove_option(&mut self.cancelled_local, option)
+				{
+					// e.g. ECHO asked for at a password prompt that was already answered
+					remove_option(&mut self.pending_local, option);
+					command(WONT, option)
+				}
+				else if remove_option(&mut self.pending_local, option)
+				{
+					add_option(&mut self.options.local, option);
+					Vec::new()
+				}
+				else if self.options.is_local(option)
+				{
+					Vec::new()
+				}
+				else if SUPPORTED_LOCAL.contains(&option)
+				{
+					add_option(&mut self.options.local, option);
+					command(WILL, option)
+				}
+				else
+				{
+					command(WONT, option)
+				}
+			},
+			DONT =>
+			{
+				remove_option(&mut self.cancelled_local, option);
+				if remove_option(&mut self.pending_local, option)
+				{
+					Vec::new()
+				}
+				else if remove_option(&mut self.options.local, option)
+				{
+					command(WONT, option)
+				}
+				else
+				{
+					Vec::new()
+				}
+			},
+			_ => Vec::new(),
+		}
+	}
+
+	/// Ask to turn one of our own options on or off, e.g. ECHO while a password is typed.
+	///
+	/// Returns the bytes to send, empty when nothing needs to change.
+	pub fn request_local(&mut self, option: u8, enable: bool) -> Vec<u8>
+	{
+		if enable
+		{
+			// still waiting on our WILL, it counts again once answered
+			remove_option(&mut self.cancelled_local, option);
+			if self.options.is_local(option) || self.pending_local.contains(&option)
+			{
+				return Vec::new();
+			}
+			add_option(&mut self.pending_local, option);
+			command(WILL, option)
+		}
+		else
+		{
+			if self.pending_local.contains(&option)
+			{
+				// the WILL is out already, turn the answer down when it comes
+				add_option(&mut self.cancelled_local, option);
+				return Vec::new();
+			}
+			if remove_option(&mut self.options.local, option)
+			{
+				command(WONT, option)
+			}
+			else
+			{
+				Vec::new()
+			}
+		}
+	}
+
+	/// Ask the client to turn one of its options on or off
+	pub fn request_remote(&mut self, option: u8, enable: bool) -> Vec<u8>
+	{
+		if enable
+		{
+			remove_option(&mut self.cancelled_remote, option);
+			if self.options.is_remote(option) || self.pending_remote.contains(&option)
+			{
+				return Vec::new();
+			}
+			add_option(&mut self.pending_remote, option);
+			command(DO, option)
+		}
+		else
+		{
+			if self.pending_remote.contains(&option)
+			{
+				add_option(&mut self.cancelled_remote, option);
+				return Vec::new();
+			}
+			if remove_option(&mut self.options.remote, option)
+			{
+				command(DONT, option)
+			}
+			else
+			{
+				Vec::new()
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn escapes_and_unescapes_iac()
+	{
+		assert_eq!(escape(&[b'a', IAC, b'b']), vec![b'a', IAC, IAC, b'b']);
+		let mut codec = TelnetCodec::new();
+		let decoded = codec.decode(&[b'a', IAC, IAC, b'b']);
+		assert_eq!(decoded.data, vec![b'a', IAC, b'b']);
+		assert!(decoded.reply.is_empty());
+	}
+
+	#[test]
+	fn keeps_sequences_split_across_reads()
+	{
+		let mut codec = TelnetCodec::new();
+		assert_eq!(codec.decode(&[b'h', IAC]).data, vec![b'h']);
+		let decoded = codec.decode(&[DO, OPT_SGA, b'i']);
+		assert_eq!(decoded.data, vec![b'i']);
+		assert_eq!(decoded.reply, vec![IAC, WILL, OPT_SGA]);
+
+		assert!(codec.decode(&[IAC, SB, OPT_TTYPE, TTYPE_IS, b'x']).subnegotiations.is_empty());
+		let decoded = codec.decode(&[b't', IAC]);
+		assert!(decoded.subnegotiations.is_empty());
+		let decoded = codec.decode(&[SE]);
+		assert_eq!(decoded.subnegotiations, vec![(OPT_TTYPE, vec![TTYPE_IS, b'x', b't'])]);
+	}
+
+	#[test]
+	fn answers_supported_and_unsupported_options()
+	{
+		let mut codec = TelnetCodec::new();
+		// the client may perform TTYPE, the server asks for the type right away
+		assert_eq!(codec.decode(&[IAC, WILL, OPT_TTYPE]).reply, vec![IAC, DO, OPT_TTYPE, IAC, SB, OPT_TTYPE, TTYPE_SEND, IAC, SE]);
+		assert_eq!(codec.decode(&[IAC, WILL, 31]).reply, vec![IAC, DONT, 31]);
+		assert_eq!(codec.decode(&[IAC, DO, OPT_SGA]).reply, vec![IAC, WILL, OPT_SGA]);
+		assert_eq!(codec.decode(&[IAC, DO, 31]).reply, vec![IAC, WONT, 31]);
+		// the server never echoes unless it offered to
+		assert_eq!(codec.decode(&[IAC, DO, OPT_ECHO]).reply, vec![IAC, WONT, OPT_ECHO]);
+		// repeating what is already agreed gets no answer, so there are no loops
+		assert!(codec.decode(&[IAC, DO, OPT_SGA]).reply.is_empty());
+		assert!(codec.options().is_local(OPT_SGA) && codec.options().is_remote(OPT_TTYPE));
+		assert!(!codec.options().is_local(OPT_ECHO));
+	}
+
+	#[test]
+	fn turns_down_echo_that_was_taken_back()
+	{
+		let mut codec = TelnetCodec::new();
+		assert_eq!(codec.request_local(OPT_ECHO, true), vec![IAC, WILL, OPT_ECHO]);
+		// the password came in before the client answered
+		assert!(codec.request_local(OPT_ECHO, false).is_empty());
+		assert_eq!(codec.decode(&[IAC, DO, OPT_ECHO]).reply, vec![IAC, WONT, OPT_ECHO]);
+		assert!(!codec.options().is_local(OPT_ECHO));
+		assert!(codec.decode(&[IAC, DONT, OPT_ECHO]).reply.is_empty());
+
+		assert_eq!(codec.request_local(OPT_ECHO, true), vec![IAC, WILL, OPT_ECHO]);
+		assert!(codec.decode(&[IAC, DO, OPT_ECHO]).reply.is_empty());
+		assert!(codec.options().is_local(OPT_ECHO));
+		assert_eq!(codec.request_local(OPT_ECHO, false), vec![IAC, WONT, OPT_ECHO]);
+	}
+
+	#[test]
+	fn reads_the_terminal_type()
+	{
+		let mut codec = TelnetCodec::new();
+		let decoded = codec.decode(&[IAC, SB, OPT_TTYPE, TTYPE_IS, b'A', b'N', b'S', b'I', IAC, SE, b'x']);
+		assert_eq!(decoded.data, vec![b'x']);
+		let (option, ref payload) = decoded.subnegotiations[0];
+		assert_eq!(option, OPT_TTYPE);
+		assert_eq!(terminal_type(payload), Some("ANSI".to_string()));
+		assert_eq!(terminal_type(&[TTYPE_SEND]), None);
+	}
+
+	#[test]
+	fn drops_overlong_subnegotiations()
+	{
+		let mut codec = TelnetCodec::new();
+		let mut input = vec![IAC, SB, OPT_TTYPE, TTYPE_IS];
+		input.extend(vec![b'a'; MAX_SUBNEGOTIATION * 4]);
+		input.extend(vec![IAC, SE, b'o', b'k']);
+		let decoded = codec.decode(&input);
+		assert!(decoded.subnegotiations.is_empty());
+		assert_eq!(decoded.data, b"ok".to_vec());
+		assert!(codec.subnegotiation.len() <= MAX_SUBNEGOTIATION);
+	}
+
+	#[test]
+	fn drops_the_nul_after_a_carriage_return()
+	{
+		let mut codec = TelnetCodec::new();
+		assert_eq!(codec.decode(b"a\r\0b\r\n").data, b"a\rb\r\n".to_vec());
+	}
+}
@@ -6,23 +6,27 @@ use std::path::Path;
 use std;
 use std::io;
 use std::fmt;
+use std::sync::mpsc;
+use std::thread;
 
 pub struct Transaction
 {
 	pub id: i32,
 	pub message: String,
 	pub time_created: time::Timespec,
+	pub target: String,
 }
 
 impl Transaction
 {
-	pub fn new(msg:String, created_time: time::Timespec) -> Transaction
+	pub fn new(msg:String, created_time: time::Timespec, target: String) -> Transaction
 	{
 		Transaction
 		{
 			id: 0,
 			message: msg,
-			time_created: created_time
+			time_created: created_time,
+			target: target,
 		}
 	}
 }
@@ -63,7 +67,7 @@ impl SqliteDB
 		}
 	}
 	
-	pub fn createDB(self)
+	pub fn createDB(&self)
 	{
 		// https://github.com/jgallagher/rusqlite
 		let result = self.dbconn.execute("CREATE TABLE IF NOT EXISTS messages (
@@ -74,36 +78,124 @@ impl SqliteDB
 		  )", &[]).unwrap();
 	}
 	
-	pub fn insertRecord(self, record:Transaction)
+	pub fn insertRecord(&self, record: &Transaction) -> rusqlite::Result<()>
 	{
-		let target = String::from("all");
-		
-		self.dbconn.execute("INSERT INTO messages (message, time_created, target)
-		VALUES ($1, $2, $3)", &[&record.message, &record.time_created, &target]).unwrap();
+		try!(self.dbconn.execute("INSERT INTO messages (message, time_created, target)
+		VALUES ($1, $2, $3)", &[&record.message, &record.time_created, &record.target]));
+		Ok(())
 	}
 	
-	pub fn getRecord(self, updatedTime: time::Timespec) -> Vec<Transaction>
+	/// Messages logged after since, only those sent to target when one is given, oldest first
+	pub fn history(&self, since: time::Timespec, target: Option<&str>) -> rusqlite::Result<Vec<Transaction>>
 	{
-		let mut stmt = self.dbconn.prepare("SELECT id, message, time_created FROM messages 
-		WHERE time_created > ?").unwrap();
-		let pending_messages = stmt.query_map(&[&updatedTime], |row|
+		let mut stmt = try!(self.dbconn.prepare("SELECT id, message, time_created, target FROM messages
+		WHERE time_created > $1 AND ($2 IS NULL OR target = $2) ORDER BY id"));
+		let rows = try!(stmt.query_map(&[&since, &target], |row|
 		{
 			Transaction
 			{
 				id: row.get(0),
 				message: row.get(1),
-				time_created: row.get(2)
+				time_created: row.get(2),
+				target: row.get(3)
 			}
-		}).unwrap();
+		}));
 		
 		let mut messages = Vec::new();
-		for message in pending_messages
+		for message in rows
 		{
-			messages.push(message.unwrap());
+			messages.push(try!(message));
 		}
-		messages
+		Ok(messages)
 	}
 }
 
+/// Append-only message log for history and audit.
+///
+/// Records are written by a background thread so the event loop never waits on SQLite.
+pub struct TransactionLog
+{
+	// None once the log is closed
+	sender: Option<mpsc::Sender<Transaction>>,
+	writer: Option<thread::JoinHandle<()>>,
+}
 
+impl TransactionLog
+{
+	pub fn new(filepath: &str) -> TransactionLog
+	{
+		let (sender, receiver) = mpsc::channel::<Transaction>();
+		let path = String::from(filepath);
+		
+		let writer = thread::spawn(move ||
+		{
+			let db = SqliteDB::new(&path);
+			db.createDB();
+			
+			// ends once the sender is dropped
+			for record in receiver
+			{
+				// one lost record is no reason to stop logging the rest
+				if let Err(e) = db.insertRecord(&record)
+				{
+					println!("Failed to log message {:?}: {}", record.message, e);
+				}
+			}
+		});
+		
+		TransactionLog
+		{
+			sender: Some(sender),
+			writer: Some(writer),
+		}
+	}
+	
+	pub fn append(&self, record: Transaction)
+	{
+		match self.sender
+		{
+			Some(ref sender) => match sender.send(record)
+			{
+				Ok(_) => {},
+				Err(e) => println!("Transaction log writer is gone, dropping {:?}", e.0.message),
+			},
+			None => println!("Transaction log is closed, dropping {:?}", record.message),
+		}
+	}
+	
+	/// Wait for every record appended so far to be written, then stop the writer
+	pub fn close(&mut self)
+	{
+		self.sender = None;
+		if let Some(writer) = self.writer.take()
+		{
+			if writer.join().is_err()
+			{
+				println!("Transaction log writer failed");
+			}
+		}
+	}
+}
 
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	
+	#[test]
+	fn history_filters_by_target()
+	{
+		let db = SqliteDB::new(":memory:");
+		db.createDB();
+		let start = time::Timespec::new(1000, 0);
+		let later = time::Timespec::new(2000, 0);
+		db.insertRecord(&Transaction::new("hello square".to_string(), later, "room:square".to_string())).unwrap();
+		db.insertRecord(&Transaction::new("psst".to_string(), later, "user:Alice".to_string())).unwrap();
+		db.insertRecord(&Transaction::new("still here".to_string(), later, "room:square".to_string())).unwrap();
+		
+		let square: Vec<String> = db.history(start, Some("room:square")).unwrap().into_iter().map(|t| t.message).collect();
+		assert_eq!(square, vec!["hello square".to_string(), "still here".to_string()]);
+		assert_eq!(db.history(start, None).unwrap().len(), 3);
+		assert!(db.history(later, None).unwrap().is_empty());
+	}
+}
@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::collections::hash_map::{Values, ValuesMut};
+
+use character::Character;
+use item::{Item, ItemPrototype};
+use mob::{MobPrototype, Population};
+
+// room every connection starts in
+pub const DEFAULT_ROOM: &'static str = "limbo";
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum Direction
+{
+	North,
+	East,
+	South,
+	West,
+	Up,
+	Down,
+}
+
+// the order exits are listed in
+pub const DIRECTIONS: [Direction; 6] = [Direction::North, Direction::East, Direction::South,
+	Direction::West, Direction::Up, Direction::Down];
+
+impl Direction
+{
+	pub fn name(&self) -> &'static str
+	{
+		match *self
+		{
+			Direction::North => "north",
+			Direction::East => "east",
+			Direction::South => "south",
+			Direction::West => "west",
+			Direction::Up => "up",
+			Direction::Down => "down",
+		}
+	}
+
+	pub fn short_name(&self) -> &'static str
+	{
+		&self.name()[..1]
+	}
+
+	pub fn opposite(&self) -> Direction
+	{
+		match *self
+		{
+			Direction::North => Direction::South,
+			Direction::East => Direction::West,
+			Direction::South => Direction::North,
+			Direction::West => Direction::East,
+			Direction::Up => Direction::Down,
+			Direction::Down => Direction::Up,
+		}
+	}
+
+	// accepts the full name or its first letter
+	pub fn parse(name: &str) -> Option<Direction>
+	{
+		let name = name.to_lowercase();
+		for direction in DIRECTIONS.iter()
+		{
+			if direction.name() == name || direction.short_name() == name
+			{
+				return Some(*direction);
+			}
+		}
+		None
+	}
+
+	/// Where someone arriving through this direction came from, e.g. "the south" or "below"
+	pub fn arrival(&self) -> String
+	{
+		match *self
+		{
+			Direction::Up => "below".to_string(),
+			Direction::Down => "above".to_string(),
+			_ => format!("the {}", self.opposite().name()),
+		}
+	}
+}
+
+pub struct Room
+{
+	pub id: String,
+	pub title: String,
+	pub description: String,
+	pub exits: HashMap<Direction, String>,
+	// items lying on the floor
+	pub items: Vec<Item>,
+	// the area the room was loaded from, wandering mobs never leave it
+	pub area: String,
+}
+
+impl Room
+{
+	pub fn new(id: &str, title: &str, description: &str) -> Room
+	{
+		Room
+		{
+			id: id.to_string(),
+			title: title.to_string(),
+			description: description.to_string(),
+			exits: HashMap::new(),
+			items: Vec::new(),
+			area: String::new(),
+		}
+	}
+
+	pub fn exit(mut self, direction: Direction, room: &str) -> Room
+	{
+		self.exits.insert(direction, room.to_string());
+		self
+	}
+
+	/// Title, description and exits
+	pub fn describe(&self) -> String
+	{
+		let mut exits = Vec::new();
+		for direction in DIRECTIONS.iter()
+		{
+			if self.exits.contains_key(direction)
+			{
+				exits.push(direction.name());
+			}
+		}
+
+		let exits = if exits.is_empty() { "none".to_string() } else { exits.join(" ") };
+		format!("{{C{}{{x\r\n{}\r\n[Exits: {{g{}{{x]\r\n", self.title, self.description, exits)
+	}
+}
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ResetKind
+{
+	Item,
+	Mob,
+}
+
+/// Keeps up to max copies of a prototype in a room
+pub struct Reset
+{
+	pub kind: ResetKind,
+	pub prototype: String,
+	pub room: String,
+	pub max: u32,
+}
+
+/// Every room of the game by id, and the prototypes and resets that populate them
+pub struct World
+{
+	rooms: HashMap<String, Room>,
+	items: HashMap<String, ItemPrototype>,
+	mobs: HashMap<String, MobPrototype>,
+	resets: Vec<Reset>,
+	// the mobs the resets spawned
+	population: Population,
+	// characters of everyone playing, by username
+	players: HashMap<String, Character>,
+	// where new characters and anyone in a missing room end up
+	start_room: String,
+}
+
+impl World
+{
+	pub fn new(start_room: &str) -> World
+	{
+		World
+		{
+			rooms: HashMap::new(),
+			items: HashMap::new(),
+			mobs: HashMap::new(),
+			resets: Vec::new(),
+			population: Population::new(),
+			players: HashMap::new(),
+			start_room: start_room.to_string(),
+		}
+	}
+
+	/// The rooms built in to the server
+	pub fn default_world() -> World
+	{
+		let mut world = World::new(DEFAULT_ROOM);
+		world.add_room(Room::new(DEFAULT_ROOM, "Limbo",
+			"A grey haze hangs in every direction. A path leads north towards the sound of voices.")
+			.exit(Direction::North, "square"));
+		world.add_room(Room::new("square", "Town Square",
+			"Market stalls line the square. Streets lead east and west, a tower rises above you.")
+			.exit(Direction::South, DEFAULT_ROOM)
+			.exit(Direction::East, "east_street")
+			.exit(Direction::West, "west_street")
+			.exit(Direction::Up, "tower"));
+		world.add_room(Room::new("east_street", "East Street",
+			"A quiet street of closed shutters. The square is back to the west.")
+			.exit(Direction::West, "square"));
+		world.add_room(Room::new("west_street", "West Street",
+			"Smoke drifts from a smithy. The square is back to the east.")
+			.exit(Direction::East, "square"));
+		world.add_room(Room::new("tower", "Watch Tower",
+			"The whole town is visible from up here. A ladder leads down.")
+			.exit(Direction::Down, "square"));
+		world
+	}
+
+	pub fn add_room(&mut self, room: Room)
+	{
+		self.rooms.insert(room.id.clone(), room);
+	}
+
+	pub fn get_room(&self, id: &str) -> Option<&Room>
+	{
+		self.rooms.get(id)
+	}
+
+	pub fn get_room_mut(&mut self, id: &str) -> Option<&mut Room>
+	{
+		self.rooms.get_mut(id)
+	}
+
+	pub fn add_item(&mut self, item: ItemPrototype)
+	{
+		self.items.insert(item.id.clone(), item);
+	}
+
+	pub fn get_item(&self, id: &str) -> Option<&ItemPrototype>
+	{
+		self.items.get(id)
+	}
+
+	/// The name of an item, items whose prototype was removed from the areas still show up
+	pub fn item_name(&self, item: &Item) -> String
+	{
+		match self.get_item(&item.prototype)
+		{
+			Some(prototype) => prototype.name.clone(),
+			None => "something".to_string(),
+		}
+	}
+
+	/// Index of the first item in items that word refers to
+	pub fn find_item(&self, items: &Vec<Item>, word: &str) -> Option<usize>
+	{
+		items.iter().position(|item| self.get_item(&item.prototype).map(|p| p.matches(word)).unwrap_or(false))
+	}
+
+	/// Total weight of a list of items
+	pub fn weight_of(&self, items: &Vec<Item>) -> i32
+	{
+		items.iter().filter_map(|item| self.get_item(&item.prototype)).map(|p| p.weight).sum()
+	}
+
+	/// Weight of everything a character carries or wears
+	pub fn carried_weight(&self, character: &Character) -> i32
+	{
+		let worn: Vec<Item> = character.equips.iter().map(|&(_, ref item)| item.clone()).collect();
+		self.weight_of(&character.items) + self.weight_of(&worn)
+	}
+
+	pub fn add_mob(&mut self, mob: MobPrototype)
+	{
+		self.mobs.insert(mob.id.clone(), mob);
+	}
+
+	pub fn get_mob(&self, id: &str) -> Option<&MobPrototype>
+	{
+		self.mobs.get(id)
+	}
+
+	pub fn add_reset(&mut self, reset: Reset)
+	{
+		self.resets.push(reset);
+	}
+
+	pub fn resets(&self) -> &Vec<Reset>
+	{
+		&self.resets
+	}
+
+	/// Top up the items and mobs in every room to what its resets ask for
+	pub fn reset(&mut self)
+	{
+		for (index, reset) in self.resets.iter().enumerate()
+		{
+			let room = match self.rooms.get_mut(&reset.room)
+			{
+				Some(room) => room,
+				None => continue,
+			};
+
+			match reset.kind
+			{
+				ResetKind::Item =>
+				{
+					let present = room.items.iter().filter(|item| item.prototype == reset.prototype).count() as u32;
+					for _ in present..reset.max
+					{
+						room.items.push(Item::new(&reset.prototype));
+					}
+				},
+				ResetKind::Mob =>
+				{
+					// a mob counts against its reset wherever it wandered off to, until it dies
+					let prototype = match self.mobs.get(&reset.prototype)
+					{
+						Some(prototype) => prototype,
+						None => continue,
+					};
+					for _ in self.population.alive_from(index)..reset.max
+					{
+						self.population.spawn(prototype, &room.id, &room.area, Some(index));
+					}
+				},
+			}
+		}
+	}
+
+	pub fn population(&self) -> &Population
+	{
+		&self.population
+	}
+
+	pub fn population_mut(&mut self) -> &mut Population
+	{
+		&mut self.population
+	}
+
+	/// The first mob in a room that word refers to
+	pub fn find_mob(&self, room: &str, word: &str) -> Option<u32>
+	{
+		self.population.in_room(room).into_iter().find(|id|
+		{
+			self.population.get(*id)
+				.and_then(|mob| self.get_mob(&mob.prototype))
+				.map(|prototype| prototype.matches(word))
+				.unwrap_or(false)
+		})
+	}
+
+	// players are keyed by their exact account name, accounts that differ only in case are different players
+
+	/// Put a character in the world, returns false and leaves the world alone if one of that name is already in it
+	pub fn add_player(&mut self, character: Character) -> bool
+	{
+		if self.players.contains_key(&character.username)
+		{
+			return false;
+		}
+		self.players.insert(character.username.clone(), character);
+		true
+	}
+
+	pub fn remove_player(&mut self, username: &str) -> Option<Character>
+	{
+		self.players.remove(username)
+	}
+
+	pub fn player(&self, username: &str) -> Option<&Character>
+	{
+		self.players.get(username)
+	}
+
+	pub fn player_mut(&mut self, username: &str) -> Option<&mut Character>
+	{
+		self.players.get_mut(username)
+	}
+
+	/// The player a typed name refers to, the exact name first and otherwise the only one it matches ignoring case
+	pub fn find_player(&self, name: &str) -> Option<&Character>
+	{
+		if let Some(character) = self.players.get(name)
+		{
+			return Some(character);
+		}
+		let name = name.to_lowercase();
+		let mut matches = self.players.values().filter(|c| c.username.to_lowercase() == name);
+		match (matches.next(), matches.next())
+		{
+			(Some(character), None) => Some(character),
+			_ => None,
+		}
+	}
+
+	pub fn players<'a>(&'a self) -> Values<'a, String, Character>
+	{
+		self.players.values()
+	}
+
+	pub fn players_mut<'a>(&'a mut self) -> ValuesMut<'a, String, Character>
+	{
+		self.players.values_mut()
+	}
+
+	/// Room description with the items on the floor, the mobs and everyone else who is there
+	pub fn describe_room(&self, id: &str, viewer: &str) -> String
+	{
+		let room = match self.get_room(id)
+		{
+			Some(room) => room,
+			None => return String::new(),
+		};
+
+		let mut text = room.describe();
+		for item in room.items.iter()
+		{
+			text = text + &format!("{} is lying here.\r\n", capitalize(&self.item_name(item)));
+		}
+		for id in self.population.in_room(id)
+		{
+			// a mob shows its description where a player just shows up by name
+			let line = match self.population.get(id).and_then(|mob| self.get_mob(&mob.prototype))
+			{
+				Some(prototype) if !prototype.description.is_empty() => prototype.description.clone(),
+				Some(prototype) => format!("{} is here.", capitalize(&prototype.name)),
+				None => continue,
+			};
+			text = text + &line + "\r\n";
+		}
+
+		let mut players: Vec<&str> = self.players.values()
+			.filter(|c| c.room == id && c.username != viewer)
+			.map(|c| &c.username[..])
+			.collect();
+		players.sort();
+		for player in players
+		{
+			text = text + &format!("{} is here.\r\n", player);
+		}
+		text
+	}
+
+	pub fn start_room(&self) -> &str
+	{
+		&self.start_room
+	}
+
+	pub fn set_start_room(&mut self, id: &str)
+	{
+		self.start_room = id.to_string();
+	}
+
+	/// The room an exit leads to, None when there is no such exit
+	pub fn follow_exit(&self, from: &str, direction: Direction) -> Option<&Room>
+	{
+		self.get_room(from)
+			.and_then(|room| room.exits.get(&direction))
+			.and_then(|to| self.get_room(to))
+	}
+}
+
+pub fn capitalize(text: &str) -> String
+{
+	let mut chars = text.chars();
+	match chars.next()
+	{
+		Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+		None => String::new(),
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use item::ItemType;
+
+	#[test]
+	fn parses_directions()
+	{
+		assert_eq!(Direction::parse("n"), Some(Direction::North));
+		assert_eq!(Direction::parse("Down"), Some(Direction::Down));
+		assert_eq!(Direction::parse("sideways"), None);
+		assert_eq!(Direction::Up.opposite(), Direction::Down);
+	}
+
+	fn player(name: &str) -> Character
+	{
+		let mut character = Character::new();
+		character.username = name.to_string();
+		character
+	}
+
+	#[test]
+	fn players_go_by_their_exact_name()
+	{
+		let mut world = World::new("start");
+		assert!(world.add_player(player("swu")));
+		assert!(!world.add_player(player("swu")));
+		assert!(world.add_player(player("SWU")));
+		assert!(world.add_player(player("Alice")));
+
+		assert_eq!(world.player("SWU").map(|c| &c.username[..]), Some("SWU"));
+		assert!(world.player("alice").is_none());
+		// typed names match the exact name first, and ignore case only when that is unambiguous
+		assert_eq!(world.find_player("swu").map(|c| &c.username[..]), Some("swu"));
+		assert_eq!(world.find_player("alice").map(|c| &c.username[..]), Some("Alice"));
+		assert!(world.find_player("Swu").is_none());
+	}
+
+	#[test]
+	fn follows_exits()
+	{
+		let world = World::default_world();
+		assert_eq!(world.follow_exit(DEFAULT_ROOM, Direction::North).map(|r| &r.id[..]), Some("square"));
+		assert!(world.follow_exit(DEFAULT_ROOM, Direction::South).is_none());
+	}
+
+	#[test]
+	fn resets_and_finds_items()
+	{
+		let mut world = World::default_world();
+		world.add_item(ItemPrototype
+		{
+			id: "sword".to_string(),
+			name: "a short sword".to_string(),
+			keywords: vec!["sword".to_string(), "short".to_string()],
+			description: String::new(),
+			weight: 5,
+			value: 10,
+			item_type: ItemType::Weapon,
+			flags: Vec::new(),
+			wear: None,
+			modifiers: Vec::new(),
+		});
+		world.add_reset(Reset { kind: ResetKind::Item, prototype: "sword".to_string(), room: DEFAULT_ROOM.to_string(), max: 2 });
+
+		world.reset();
+		world.reset();
+		let items = &world.get_room(DEFAULT_ROOM).unwrap().items;
+		assert_eq!(items.len(), 2);
+		assert_eq!(world.find_item(items, "sw"), Some(0));
+		assert_eq!(world.find_item(items, "axe"), None);
+		assert_eq!(world.weight_of(items), 10);
+	}
+}